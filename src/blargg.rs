@@ -0,0 +1,172 @@
+use crate::components::apu::APU;
+use crate::components::assembly::Assembler;
+use crate::components::bus::BUS;
+use crate::components::cartridge::Rom;
+use crate::components::cpu::CPU;
+use crate::components::joypads::Joypad;
+use crate::components::ppu::PPU;
+
+// blargg's test ROMs report through cartridge RAM:
+//
+// $6000       status: $80 = running, $81 = press reset, $00-$7F = result (0 = passed)
+// $6001-$6003 signature DE B0 61, the status is only valid once it is written
+// $6004-      zero terminated text output
+const STATUS: u16 = 0x6000;
+const SIGNATURE: u16 = 0x6001;
+const MESSAGE: u16 = 0x6004;
+
+const RUNNING: u8 = 0x80;
+const RESET_REQUESTED: u8 = 0x81;
+
+const CPU_CLOCK: usize = 1_789_773;
+// the ROM wants reset pressed at least 100ms after asking for it, wait 200ms
+const RESET_DELAY_CYCLES: usize = CPU_CLOCK / 5;
+
+/// Runs a test ROM that uses the $6000 protocol without video or audio output.
+///
+/// Returns the ROM's text output when it passes, or the result code and text
+/// when it fails or does not finish within `timeout_seconds` of emulated time.
+pub fn run(rom: Rom, timeout_seconds: usize) -> Result<String, String> {
+    let bus = BUS::new(rom, |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {});
+    let mut cpu = CPU::new(bus);
+    cpu.reset();
+
+    // stepped by hand instead of run_with_callback, which only returns once
+    // the CPU halts: the runner has to stop on a result or a timeout, and
+    // presses reset between two instructions
+    let assembler = Assembler::new();
+    let mut reset_at = None;
+    loop {
+        if cpu.step(&assembler, &mut |_| {}) {
+            return Err(format!("CPU halted\n{}", message(&mut cpu)));
+        }
+
+        let cycles = cpu.bus.cycles();
+        if cycles > timeout_seconds * CPU_CLOCK {
+            return Err(format!("timed out\n{}", message(&mut cpu)));
+        }
+
+        if !has_signature(&mut cpu) {
+            continue;
+        }
+
+        match cpu.memory_read(STATUS) {
+            RUNNING => {}
+            RESET_REQUESTED => match reset_at {
+                None => reset_at = Some(cycles + RESET_DELAY_CYCLES),
+                Some(at) if cycles >= at => {
+                    cpu.bus.reset();
+                    cpu.reset();
+                    reset_at = None;
+                }
+                Some(_) => {}
+            },
+            0 => return Ok(message(&mut cpu)),
+            code => return Err(format!("failed with ${:02X}\n{}", code, message(&mut cpu))),
+        }
+    }
+}
+
+fn has_signature(cpu: &mut CPU) -> bool {
+    [0xDE, 0xB0, 0x61]
+        .iter()
+        .enumerate()
+        .all(|(i, &byte)| cpu.memory_read(SIGNATURE + i as u16) == byte)
+}
+
+fn message(cpu: &mut CPU) -> String {
+    let mut text = vec![];
+    for address in MESSAGE..=0x7FFF {
+        match cpu.memory_read(address) {
+            0 => break,
+            byte => text.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&text).trim_end().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::components::cartridge::test::nrom;
+
+    // LDA #value, STA address
+    fn sta(value: u8, address: u16) -> Vec<u8> {
+        vec![0xA9, value, 0x8D, address as u8, (address >> 8) as u8]
+    }
+
+    // JMP to itself at the end of the program
+    fn jmp_self(program: &mut Vec<u8>) {
+        let address = 0x8000 + program.len() as u16;
+        program.extend([0x4C, address as u8, (address >> 8) as u8]);
+    }
+
+    // marks the test as running, writes the signature and "ok" as output
+    fn preamble() -> Vec<u8> {
+        let mut program = sta(RUNNING, STATUS);
+        program.extend(sta(0xDE, 0x6001));
+        program.extend(sta(0xB0, 0x6002));
+        program.extend(sta(0x61, 0x6003));
+        program.extend(sta(b'o', 0x6004));
+        program.extend(sta(b'k', 0x6005));
+        program.extend(sta(0, 0x6006));
+        program
+    }
+
+    // NROM with the program at $8000 and the reset vector pointing to it
+    fn program_rom(program: Vec<u8>) -> Rom {
+        let mut pgp_rom = vec![0xEA; 0x4000];
+        pgp_rom[..program.len()].copy_from_slice(&program);
+        pgp_rom[0x3FFC] = 0x00;
+        pgp_rom[0x3FFD] = 0x80;
+        nrom(pgp_rom)
+    }
+
+    fn result_rom(status: u8) -> Rom {
+        let mut program = preamble();
+        program.extend(sta(status, STATUS));
+        jmp_self(&mut program);
+        program_rom(program)
+    }
+
+    #[test]
+    fn test_passed() {
+        assert_eq!(run(result_rom(0), 1), Ok(String::from("ok")));
+    }
+
+    #[test]
+    fn test_failed() {
+        assert_eq!(
+            run(result_rom(3), 1),
+            Err(String::from("failed with $03\nok"))
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut program = preamble();
+        jmp_self(&mut program);
+        assert_eq!(
+            run(program_rom(program), 1),
+            Err(String::from("timed out\nok"))
+        );
+    }
+
+    #[test]
+    fn test_reset_request() {
+        let mut program = preamble();
+        program.extend([0xAD, 0x00, 0x03]); // LDA $0300
+        let branch = program.len();
+        program.extend([0xD0, 0x00]); // BNE after_reset
+        program.extend([0xEE, 0x00, 0x03]); // INC $0300
+        program.extend(sta(RESET_REQUESTED, STATUS));
+        jmp_self(&mut program);
+
+        // after_reset: RAM survives the reset, report a pass
+        program[branch + 1] = (program.len() - branch - 2) as u8;
+        program.extend(sta(0, STATUS));
+        jmp_self(&mut program);
+
+        assert_eq!(run(program_rom(program), 1), Ok(String::from("ok")));
+    }
+}
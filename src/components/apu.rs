@@ -0,0 +1,912 @@
+use super::cartridge::Region;
+
+// 2A03 APU: two pulse channels, triangle, noise and DMC mixed into a mono
+// sample stream. Everything here is clocked in CPU cycles.
+//
+//  $4000-$4003 Pulse 1     $4008-$400B Triangle    $4010-$4013 DMC
+//  $4004-$4007 Pulse 2     $400C-$400F Noise       $4015 Status, $4017 Frame counter
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15,
+];
+
+// The 2A07 (PAL) runs from a slower CPU clock with its own period tables,
+// the Dendy clone keeps the NTSC tables
+struct Timing {
+    cpu_clock: f64,
+    noise_periods: [u16; 16],
+    dmc_rates: [u16; 16],
+    // frame sequencer steps, in CPU cycles since the last $4017 write or wrap
+    frame_steps: [usize; 5],
+}
+
+const NTSC_TIMING: Timing = Timing {
+    cpu_clock: 1_789_773.0,
+    noise_periods: [
+        4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+    ],
+    dmc_rates: [
+        428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+    ],
+    frame_steps: [7457, 14913, 22371, 29829, 37281],
+};
+
+const PAL_TIMING: Timing = Timing {
+    cpu_clock: 1_662_607.0,
+    noise_periods: [
+        4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+    ],
+    dmc_rates: [
+        398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+    ],
+    frame_steps: [8313, 16627, 24939, 33252, 41565],
+};
+
+const DENDY_TIMING: Timing = Timing {
+    cpu_clock: 1_773_448.0,
+    ..NTSC_TIMING
+};
+
+struct Envelope {
+    start: bool,
+    loop_flag: bool,
+    constant_volume: bool,
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope {
+            start: false,
+            loop_flag: false,
+            constant_volume: false,
+            volume: 0,
+            divider: 0,
+            decay: 0,
+        }
+    }
+
+    fn write(&mut self, data: u8) {
+        self.loop_flag = data & 0b0010_0000 != 0;
+        self.constant_volume = data & 0b0001_0000 != 0;
+        self.volume = data & 0b1111;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.loop_flag {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    fn new() -> Self {
+        LengthCounter {
+            enabled: false,
+            halt: false,
+            counter: 0,
+        }
+    }
+
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[index as usize];
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    reload: bool,
+    divider: u8,
+}
+
+struct Pulse {
+    // pulse 1 negates in one's complement, pulse 2 in two's complement
+    ones_complement: bool,
+    duty: u8,
+    sequence_pos: u8,
+    timer_period: u16,
+    timer: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+    sweep: Sweep,
+}
+
+impl Pulse {
+    fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            duty: 0,
+            sequence_pos: 0,
+            timer_period: 0,
+            timer: 0,
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+            sweep: Sweep {
+                enabled: false,
+                period: 0,
+                negate: false,
+                shift: 0,
+                reload: false,
+                divider: 0,
+            },
+        }
+    }
+
+    fn write_control(&mut self, data: u8) {
+        self.duty = data >> 6;
+        self.length.halt = data & 0b0010_0000 != 0;
+        self.envelope.write(data);
+    }
+
+    fn write_sweep(&mut self, data: u8) {
+        self.sweep.enabled = data & 0b1000_0000 != 0;
+        self.sweep.period = (data >> 4) & 0b111;
+        self.sweep.negate = data & 0b1000 != 0;
+        self.sweep.shift = data & 0b111;
+        self.sweep.reload = true;
+    }
+
+    fn write_timer_low(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x0700) | data as u16;
+    }
+
+    fn write_timer_high(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+        self.length.load(data >> 3);
+        self.sequence_pos = 0;
+        self.envelope.start = true;
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_pos = (self.sequence_pos + 1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn target_period(&self) -> u16 {
+        let change = self.timer_period >> self.sweep.shift;
+        if self.sweep.negate {
+            let change = change + self.ones_complement as u16;
+            self.timer_period.saturating_sub(change)
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.timer_period < 8 || self.target_period() > 0x7FF
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep.divider == 0 && self.sweep.enabled && self.sweep.shift > 0 && !self.muted()
+        {
+            self.timer_period = self.target_period();
+        }
+
+        if self.sweep.divider == 0 || self.sweep.reload {
+            self.sweep.divider = self.sweep.period;
+            self.sweep.reload = false;
+        } else {
+            self.sweep.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.length.active()
+            || self.muted()
+            || DUTY_TABLE[self.duty as usize][self.sequence_pos as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+struct Triangle {
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    sequence_pos: u8,
+    timer_period: u16,
+    timer: u16,
+    length: LengthCounter,
+}
+
+impl Triangle {
+    fn new() -> Self {
+        Triangle {
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            sequence_pos: 0,
+            timer_period: 0,
+            timer: 0,
+            length: LengthCounter::new(),
+        }
+    }
+
+    fn write_control(&mut self, data: u8) {
+        self.control = data & 0b1000_0000 != 0;
+        self.length.halt = self.control;
+        self.linear_reload_value = data & 0b0111_1111;
+    }
+
+    fn write_timer_low(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x0700) | data as u16;
+    }
+
+    fn write_timer_high(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+        self.length.load(data >> 3);
+        self.linear_reload = true;
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length.active() && self.linear_counter > 0 {
+                self.sequence_pos = (self.sequence_pos + 1) & 0b1_1111;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear_counter(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        TRIANGLE_TABLE[self.sequence_pos as usize]
+    }
+}
+
+struct Noise {
+    periods: &'static [u16; 16],
+    mode: bool,
+    shift_register: u16,
+    timer_period: u16,
+    timer: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+}
+
+impl Noise {
+    fn new() -> Self {
+        Noise {
+            periods: &NTSC_TIMING.noise_periods,
+            mode: false,
+            shift_register: 1,
+            timer_period: NTSC_TIMING.noise_periods[0],
+            timer: 0,
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+        }
+    }
+
+    fn write_control(&mut self, data: u8) {
+        self.length.halt = data & 0b0010_0000 != 0;
+        self.envelope.write(data);
+    }
+
+    fn write_period(&mut self, data: u8) {
+        self.mode = data & 0b1000_0000 != 0;
+        self.timer_period = self.periods[(data & 0b1111) as usize];
+    }
+
+    fn write_length(&mut self, data: u8) {
+        self.length.load(data >> 3);
+        self.envelope.start = true;
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift_register & 1) ^ ((self.shift_register >> tap) & 1);
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.shift_register & 1 == 1 || !self.length.active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+struct Dmc {
+    rates: &'static [u16; 16],
+    irq_enabled: bool,
+    irq_pending: bool,
+    loop_flag: bool,
+    rate: u16,
+    timer: u16,
+    output_level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Dmc {
+    fn new() -> Self {
+        Dmc {
+            rates: &NTSC_TIMING.dmc_rates,
+            irq_enabled: false,
+            irq_pending: false,
+            loop_flag: false,
+            rate: NTSC_TIMING.dmc_rates[0],
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+
+    fn write_control(&mut self, data: u8) {
+        self.irq_enabled = data & 0b1000_0000 != 0;
+        if !self.irq_enabled {
+            self.irq_pending = false;
+        }
+        self.loop_flag = data & 0b0100_0000 != 0;
+        self.rate = self.rates[(data & 0b1111) as usize];
+    }
+
+    fn write_direct_load(&mut self, data: u8) {
+        self.output_level = data & 0b0111_1111;
+    }
+
+    fn write_sample_address(&mut self, data: u8) {
+        self.sample_address = 0xC000 + data as u16 * 64;
+    }
+
+    fn write_sample_length(&mut self, data: u8) {
+        self.sample_length = data as u16 * 16 + 1;
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn sample_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    fn fill_sample_buffer(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        self.current_address = if self.current_address == 0xFFFF {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rate - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 == 1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift_register = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+pub struct APU {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    frame_steps: &'static [usize; 5],
+    five_step_mode: bool,
+    frame_irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycles: usize,
+    cycles: usize,
+
+    cycles_per_sample: f64,
+    sample_timer: f64,
+    sample_sum: f32,
+    sample_count: u32,
+    samples: Vec<f32>,
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+
+            frame_steps: &NTSC_TIMING.frame_steps,
+            five_step_mode: false,
+            frame_irq_inhibit: false,
+            frame_irq: false,
+            frame_cycles: 0,
+            cycles: 0,
+
+            cycles_per_sample: NTSC_TIMING.cpu_clock / SAMPLE_RATE as f64,
+            sample_timer: 0.0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        let timing = match region {
+            Region::Pal => &PAL_TIMING,
+            Region::Dendy => &DENDY_TIMING,
+            Region::Ntsc | Region::MultiRegion => &NTSC_TIMING,
+        };
+
+        self.noise.periods = &timing.noise_periods;
+        self.dmc.rates = &timing.dmc_rates;
+        self.frame_steps = &timing.frame_steps;
+        self.cycles_per_sample = timing.cpu_clock / SAMPLE_RATE as f64;
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            0x4000 => self.pulse1.write_control(data),
+            0x4001 => self.pulse1.write_sweep(data),
+            0x4002 => self.pulse1.write_timer_low(data),
+            0x4003 => self.pulse1.write_timer_high(data),
+
+            0x4004 => self.pulse2.write_control(data),
+            0x4005 => self.pulse2.write_sweep(data),
+            0x4006 => self.pulse2.write_timer_low(data),
+            0x4007 => self.pulse2.write_timer_high(data),
+
+            0x4008 => self.triangle.write_control(data),
+            0x400A => self.triangle.write_timer_low(data),
+            0x400B => self.triangle.write_timer_high(data),
+
+            0x400C => self.noise.write_control(data),
+            0x400E => self.noise.write_period(data),
+            0x400F => self.noise.write_length(data),
+
+            0x4010 => self.dmc.write_control(data),
+            0x4011 => self.dmc.write_direct_load(data),
+            0x4012 => self.dmc.write_sample_address(data),
+            0x4013 => self.dmc.write_sample_length(data),
+
+            0x4015 => self.write_status(data),
+            0x4017 => self.write_frame_counter(data),
+
+            _ => { /* unused registers $4009, $400D */ }
+        }
+    }
+
+    // 7  bit  0
+    // ---- ----
+    // IF-D NT21
+    // || | ||||
+    //    | |||+- Pulse 1 length counter > 0
+    //    | ||+-- Pulse 2 length counter > 0
+    //    | |+--- Triangle length counter > 0
+    //    | +---- Noise length counter > 0
+    // ||  +------ DMC bytes remaining > 0
+    // |+--------- Frame interrupt (cleared by this read)
+    // +---------- DMC interrupt
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+        status |= self.pulse1.length.active() as u8;
+        status |= (self.pulse2.length.active() as u8) << 1;
+        status |= (self.triangle.length.active() as u8) << 2;
+        status |= (self.noise.length.active() as u8) << 3;
+        status |= ((self.dmc.bytes_remaining > 0) as u8) << 4;
+        status |= (self.frame_irq as u8) << 6;
+        status |= (self.dmc.irq_pending as u8) << 7;
+        self.frame_irq = false;
+        status
+    }
+
+    // silences every channel as if $4015 was written with 0 and restarts the
+    // frame counter in its last mode, both IRQ flags are cleared
+    pub fn reset(&mut self) {
+        self.write_status(0);
+        self.frame_irq = false;
+        let mode = (self.five_step_mode as u8) << 7 | (self.frame_irq_inhibit as u8) << 6;
+        self.write_frame_counter(mode);
+    }
+
+    fn write_status(&mut self, data: u8) {
+        self.pulse1.length.set_enabled(data & 0b0001 != 0);
+        self.pulse2.length.set_enabled(data & 0b0010 != 0);
+        self.triangle.length.set_enabled(data & 0b0100 != 0);
+        self.noise.length.set_enabled(data & 0b1000 != 0);
+        self.dmc.set_enabled(data & 0b1_0000 != 0);
+        self.dmc.irq_pending = false;
+    }
+
+    fn write_frame_counter(&mut self, data: u8) {
+        self.five_step_mode = data & 0b1000_0000 != 0;
+        self.frame_irq_inhibit = data & 0b0100_0000 != 0;
+        if self.frame_irq_inhibit {
+            self.frame_irq = false;
+        }
+        self.frame_cycles = 0;
+
+        if self.five_step_mode {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear_counter();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.length.clock();
+        self.pulse2.length.clock();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+
+    //  4-step: quarter, half, quarter, half + IRQ (wrap)
+    //  5-step: quarter, half, quarter, -, half (wrap)
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycles += 1;
+
+        let step = self.frame_steps.iter().position(|&c| c == self.frame_cycles);
+        match (step, self.five_step_mode) {
+            (Some(0), _) | (Some(2), _) => self.clock_quarter_frame(),
+            (Some(1), _) | (Some(4), true) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            (Some(3), false) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+                if !self.frame_irq_inhibit {
+                    self.frame_irq = true;
+                }
+            }
+            _ => {}
+        }
+
+        let period = if self.five_step_mode {
+            self.frame_steps[4]
+        } else {
+            self.frame_steps[3]
+        };
+        if self.frame_cycles > period {
+            self.frame_cycles = 0;
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.cycles += 1;
+
+            self.clock_frame_counter();
+
+            self.triangle.clock_timer();
+            self.noise.clock_timer();
+            self.dmc.clock_timer();
+            if self.cycles & 1 == 0 {
+                self.pulse1.clock_timer();
+                self.pulse2.clock_timer();
+            }
+
+            self.sample_sum += self.output();
+            self.sample_count += 1;
+            self.sample_timer += 1.0;
+            if self.sample_timer >= self.cycles_per_sample {
+                self.sample_timer -= self.cycles_per_sample;
+                self.samples.push(self.sample_sum / self.sample_count as f32);
+                self.sample_sum = 0.0;
+                self.sample_count = 0;
+            }
+        }
+    }
+
+    // Non-linear mixer approximation from http://wiki.nesdev.com/w/index.php/APU_Mixer
+    fn output(&self) -> f32 {
+        let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.output() as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq_pending
+    }
+
+    /// Address the DMC wants its next sample byte from, if its buffer is empty
+    pub fn dmc_sample_request(&self) -> Option<u16> {
+        self.dmc.sample_request()
+    }
+
+    pub fn dmc_fill_sample_buffer(&mut self, data: u8) {
+        self.dmc.fill_sample_buffer(data);
+    }
+
+    /// Drains the samples produced since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_length_counter_status() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4003, 0b0000_1000); // length index 1 = 254
+        assert_eq!(apu.read_status() & 1, 1);
+
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.read_status() & 1, 0);
+    }
+
+    #[test]
+    fn test_length_counter_ignores_load_when_disabled() {
+        let mut apu = APU::new();
+        apu.write_register(0x400F, 0b0000_1000);
+        assert_eq!(apu.read_status() & 0b1000, 0);
+    }
+
+    #[test]
+    fn test_half_frame_clocks_length_counter() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0000_0010);
+        apu.write_register(0x4007, 0b0001_1000); // length index 3 = 2
+        assert_eq!(apu.pulse2.length.counter, 2);
+
+        apu.tick(NTSC_TIMING.frame_steps[1] as u16);
+        assert_eq!(apu.pulse2.length.counter, 1);
+        apu.tick((NTSC_TIMING.frame_steps[3] - NTSC_TIMING.frame_steps[1]) as u16);
+        assert_eq!(apu.read_status() & 0b10, 0);
+    }
+
+    #[test]
+    fn test_pulse_produces_samples() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1011_1111); // 50% duty, constant volume 15
+        apu.write_register(0x4002, 0xFD);
+        apu.write_register(0x4003, 0b0000_1000);
+
+        apu.tick(10_000);
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), 10_000 * SAMPLE_RATE as usize / NTSC_TIMING.cpu_clock as usize);
+        assert!(samples.iter().any(|&s| s > 0.0));
+        assert!(apu.take_samples().is_empty());
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu = APU::new();
+        apu.tick(NTSC_TIMING.frame_steps[3] as u16);
+        assert!(apu.irq());
+        assert_eq!(apu.read_status() & 0b0100_0000, 0b0100_0000);
+        assert!(!apu.irq());
+
+        apu.write_register(0x4017, 0b0100_0000); // inhibit
+        apu.tick(NTSC_TIMING.frame_steps[3] as u16);
+        assert!(!apu.irq());
+
+        apu.write_register(0x4017, 0b1000_0000); // 5-step mode never raises it
+        apu.tick(NTSC_TIMING.frame_steps[4] as u16 + 1);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_pal_timing() {
+        let mut apu = APU::new();
+        apu.set_region(Region::Pal);
+        apu.tick(NTSC_TIMING.frame_steps[3] as u16);
+        assert!(!apu.irq());
+        apu.tick((PAL_TIMING.frame_steps[3] - NTSC_TIMING.frame_steps[3]) as u16);
+        assert!(apu.irq());
+
+        apu.write_register(0x400E, 0x0F);
+        assert_eq!(apu.noise.timer_period, 3778);
+        apu.write_register(0x4010, 0x0F);
+        assert_eq!(apu.dmc.rate, 50);
+    }
+
+    #[test]
+    fn test_dmc_irq() {
+        let mut apu = APU::new();
+        apu.write_register(0x4010, 0b1000_0000);
+        apu.write_register(0x4013, 0x00);
+        apu.write_register(0x4015, 0b0001_0000);
+
+        apu.dmc_fill_sample_buffer(0x00);
+        assert!(apu.irq());
+        assert_eq!(apu.read_status() & 0b1000_0000, 0b1000_0000);
+
+        apu.write_register(0x4015, 0);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_dmc_requests_sample_bytes() {
+        let mut apu = APU::new();
+        apu.write_register(0x4012, 0x01); // $C040
+        apu.write_register(0x4013, 0x00); // 1 byte
+        apu.write_register(0x4015, 0b0001_0000);
+
+        assert_eq!(apu.dmc_sample_request(), Some(0xC040));
+        apu.dmc_fill_sample_buffer(0xFF);
+        assert_eq!(apu.dmc_sample_request(), None);
+        assert_eq!(apu.read_status() & 0b1_0000, 0);
+    }
+}
@@ -306,18 +306,18 @@ lazy_static! {
         OpCode::new(0xe3, "*ISB", 2,8, AddressingMode::IndirectX),
         OpCode::new(0xf3, "*ISB", 2,8, AddressingMode::IndirectY),
 
-        OpCode::new(0x02, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x12, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x22, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x32, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x42, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x52, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x62, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x72, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x92, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0xb2, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0xd2, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0xf2, "*NOP", 1,2, AddressingMode::NoneAddressing),
+        OpCode::new(0x02, "*JAM", 1,2, AddressingMode::NoneAddressing),
+        OpCode::new(0x12, "*JAM", 1,2, AddressingMode::NoneAddressing),
+        OpCode::new(0x22, "*JAM", 1,2, AddressingMode::NoneAddressing),
+        OpCode::new(0x32, "*JAM", 1,2, AddressingMode::NoneAddressing),
+        OpCode::new(0x42, "*JAM", 1,2, AddressingMode::NoneAddressing),
+        OpCode::new(0x52, "*JAM", 1,2, AddressingMode::NoneAddressing),
+        OpCode::new(0x62, "*JAM", 1,2, AddressingMode::NoneAddressing),
+        OpCode::new(0x72, "*JAM", 1,2, AddressingMode::NoneAddressing),
+        OpCode::new(0x92, "*JAM", 1,2, AddressingMode::NoneAddressing),
+        OpCode::new(0xb2, "*JAM", 1,2, AddressingMode::NoneAddressing),
+        OpCode::new(0xd2, "*JAM", 1,2, AddressingMode::NoneAddressing),
+        OpCode::new(0xf2, "*JAM", 1,2, AddressingMode::NoneAddressing),
 
         OpCode::new(0x1a, "*NOP", 1,2, AddressingMode::NoneAddressing),
         OpCode::new(0x3a, "*NOP", 1,2, AddressingMode::NoneAddressing),
@@ -413,7 +413,12 @@ impl Assembler {
 
             /* BPL */ 0x10 => cpu.bpl(),
 
-            /* BRK */ 0x00 => return true,
+            /* BRK */
+            // the interrupt sequence takes care of the cycles and the PC
+            0x00 => {
+                cpu.brk();
+                return false;
+            }
 
             /* BVC */ 0x50 => cpu.bvc(),
 
@@ -624,9 +629,15 @@ impl Assembler {
                 cpu.isb(&opcode.mode);
             }
 
+            /* JAM */
+            // locks up the CPU on hardware, used to stop test programs
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => {
+                cpu.register_pc = pc_state - 1;
+                return true;
+            }
+
             /* NOPs */
-            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2
-            | 0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => {}
+            0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => {}
 
             /* LAX */
             0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => {
@@ -1,6 +1,12 @@
-use super::cartridge::Rom;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::apu::APU;
+use super::cartridge::{Mapper, Region, Rom};
 use super::joypads::Joypad;
+use super::mappers;
 use super::ppu::PPU;
+use crate::render::palette::Palette;
 
 //  _______________ $10000  _______________
 // | PRG-ROM       |       |               |
@@ -33,31 +39,72 @@ const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
 
+// CPU cycles stolen by the DMC when it fetches a sample byte: a halt cycle, a
+// dummy cycle, one more if needed to land the read on a get (odd) cycle, and
+// the read. Inside an OAM DMA the fetch reuses the DMA's halt and alignment
+const DMC_DMA_CYCLES: u8 = 4;
+const DMC_DMA_CYCLES_ALIGNED: u8 = 3;
+const DMC_DMA_CYCLES_DURING_OAM_DMA: u8 = 2;
+
+// register writes land in the last cycle of the instruction, the CPU resolves
+// this to the cycle the instruction ends on
+const NMI_AT_INSTRUCTION_END: usize = usize::MAX;
+
+type GameloopCallback<'call> = Box<dyn FnMut(&PPU, &mut APU, &mut Joypad) + 'call>;
+
 pub struct BUS<'call> {
     cpu_vram: [u8; 2048],
-    prg_rom: Vec<u8>,
+    mapper: Rc<RefCell<dyn Mapper>>,
     ppu: PPU,
+    apu: APU,
 
     cycles: usize,
-    gameloop_callback: Box<dyn FnMut(&PPU, &mut Joypad) + 'call>,
+    // PPU dots per CPU cycle as a fraction, 3 on NTSC and Dendy, 3.2 on PAL
+    ppu_clock_ratio: (usize, usize),
+    ppu_clock_remainder: usize,
+    // CPU cycle in which the PPU raised NMI, until the CPU has seen it
+    nmi_edge: Option<usize>,
+    // page written to $4014, copied once the writing instruction is done
+    oam_dma_page: Option<u8>,
+    oam_dma_running: bool,
+    gameloop_callback: GameloopCallback<'call>,
     joypad1: Joypad,
 }
 
 impl<'a> BUS<'a> {
     pub fn new<'call, F>(rom: Rom, gameloop_callback: F) -> BUS<'call>
     where
-        F: FnMut(&PPU, &mut Joypad) + 'call,
+        F: FnMut(&PPU, &mut APU, &mut Joypad) + 'call,
     {
-        let ppu = PPU::new(rom.chr_rom, rom.screen_mirroring);
+        let region = rom.region;
+        let mapper = mappers::new_mapper(rom);
+        let ppu = PPU::new(mapper.clone());
 
-        BUS {
+        let mut bus = BUS {
             cpu_vram: [0; 2048],
-            prg_rom: rom.prg_rom,
+            mapper,
             ppu: ppu,
+            apu: APU::new(),
             cycles: 0,
+            ppu_clock_ratio: (3, 1),
+            ppu_clock_remainder: 0,
+            nmi_edge: None,
+            oam_dma_page: None,
+            oam_dma_running: false,
             gameloop_callback: Box::from(gameloop_callback),
             joypad1: Joypad::new(),
-        }
+        };
+        bus.set_region(region);
+        bus
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+        self.ppu_clock_ratio = match region {
+            Region::Pal => (16, 5),
+            _ => (3, 1),
+        };
     }
 
     pub fn memory_read(&mut self, address: u16) -> u8 {
@@ -74,10 +121,11 @@ impl<'a> BUS<'a> {
             0x2004 => self.ppu.read_oam_data(),
             0x2007 => self.ppu.read_data(),
 
-            0x4000..=0x4015 => {
-                //ignore APU
+            0x4000..=0x4013 => {
+                // write-only APU registers
                 0
             }
+            0x4015 => self.apu.read_status(),
 
             0x4016 => self.joypad1.read(),
 
@@ -89,7 +137,7 @@ impl<'a> BUS<'a> {
                 let mirror_down_address = address & 0b00100000_00000111;
                 self.memory_read(mirror_down_address)
             }
-            0x8000..=0xFFFF => self.read_prg_rom(address),
+            0x6000..=0xFFFF => self.mapper.borrow_mut().cpu_read(address),
 
             _ => {
                 println!("Ignoring memory access at {:x}", address);
@@ -106,6 +154,10 @@ impl<'a> BUS<'a> {
             }
             0x2000 => {
                 self.ppu.write_to_control(data);
+                // enabling NMI during vblank raises it right away
+                if self.ppu.poll_nmi_interrupt().is_some() {
+                    self.nmi_edge = Some(NMI_AT_INSTRUCTION_END);
+                }
             }
             0x2001 => {
                 self.ppu.write_to_mask(data);
@@ -129,36 +181,21 @@ impl<'a> BUS<'a> {
             0x2007 => {
                 self.ppu.write_to_data(data);
             }
-            0x4000..=0x4013 | 0x4015 => {
-                //ignore APU
-            }
+            0x4000..=0x4013 | 0x4015 => self.apu.write_register(address, data),
 
             0x4016 => self.joypad1.write(data),
 
-            0x4017 => {
-                // ignore joypad 2
-            }
-
-            0x4014 => {
-                let mut buffer: [u8; 256] = [0; 256];
-                let hi: u16 = (data as u16) << 8;
-                for i in 0..256u16 {
-                    buffer[i as usize] = self.memory_read(hi + i);
-                }
+            // APU frame counter on write, joypad 2 on read
+            0x4017 => self.apu.write_register(address, data),
 
-                self.ppu.write_oam_dma(&buffer);
-
-                // todo: handle this eventually
-                // let add_cycles: u16 = if self.cycles % 2 == 1 { 514 } else { 513 };
-                // self.tick(add_cycles); //todo this will cause weird effects as PPU will have 513/514 * 3 ticks
-            }
+            0x4014 => self.oam_dma_page = Some(data),
 
             0x2008..=PPU_REGISTERS_MIRRORS_END => {
                 let mirror_down_address = address & 0b00100000_00000111;
                 self.memory_write(mirror_down_address, data);
                 // todo!("PPU is not supported yet");
             }
-            0x8000..=0xFFFF => panic!("Attempt to write to Cartridge ROM space: {:x}", address),
+            0x6000..=0xFFFF => self.mapper.borrow_mut().cpu_write(address, data),
 
             _ => {
                 println!("Ignoring memory write-access at {:x}", address);
@@ -166,6 +203,39 @@ impl<'a> BUS<'a> {
         }
     }
 
+    // the CPU halts after the instruction that wrote $4014: one cycle, plus one
+    // more to align on an odd cycle, then 256 read/write cycle pairs copying
+    // the page into OAM
+    pub fn run_oam_dma(&mut self) {
+        let page = match self.oam_dma_page.take() {
+            Some(page) => page,
+            None => return,
+        };
+
+        self.oam_dma_running = true;
+        let alignment = if self.cycles & 1 == 1 { 2 } else { 1 };
+        self.tick(alignment);
+
+        let hi: u16 = (page as u16) << 8;
+        for i in 0..256u16 {
+            let value = self.memory_read(hi + i);
+            self.tick(1);
+            self.ppu.write_to_oam_data(value);
+            self.tick(1);
+        }
+        self.oam_dma_running = false;
+    }
+
+    fn dmc_dma_cycles(&self) -> u8 {
+        if self.oam_dma_running {
+            DMC_DMA_CYCLES_DURING_OAM_DMA
+        } else if self.cycles & 1 == 1 {
+            DMC_DMA_CYCLES_ALIGNED
+        } else {
+            DMC_DMA_CYCLES
+        }
+    }
+
     pub fn memory_read_u16(&mut self, address: u16) -> u16 {
         let low = self.memory_read(address) as u16;
         let high = self.memory_read(address + 1) as u16;
@@ -181,25 +251,99 @@ impl<'a> BUS<'a> {
         self.memory_write(pos + 1, high);
     }
 
-    fn read_prg_rom(&self, mut address: u16) -> u8 {
-        address -= 0x8000;
-        if self.prg_rom.len() == 0x4000 && address >= 0x4000 {
-            //mirror if needed
-            address = address % 0x4000;
+    // runs cycle by cycle so the CPU knows which cycle an NMI was raised in
+    pub fn tick(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.cycles += 1;
+
+            self.apu.tick(1);
+
+            let (dots, cpu_cycles) = self.ppu_clock_ratio;
+            let scaled = dots + self.ppu_clock_remainder;
+            self.ppu_clock_remainder = scaled % cpu_cycles;
+
+            let new_frame = self.ppu.tick((scaled / cpu_cycles) as u8);
+            if self.ppu.poll_nmi_interrupt().is_some() {
+                self.nmi_edge = Some(self.cycles);
+            }
+            if new_frame {
+                (self.gameloop_callback)(&self.ppu, &mut self.apu, &mut self.joypad1);
+            }
+
+            // the CPU is halted while the DMC reads its next sample byte
+            if let Some(address) = self.apu.dmc_sample_request() {
+                let data = self.memory_read(address);
+                self.apu.dmc_fill_sample_buffer(data);
+                self.tick(self.dmc_dma_cycles());
+            }
         }
-        self.prg_rom[address as usize]
     }
 
-    pub fn tick(&mut self, cycles: u8) {
-        self.cycles += cycles as usize;
-        let new_frame = self.ppu.tick(cycles * 3);
-        if new_frame {
-            (self.gameloop_callback)(&self.ppu, &mut self.joypad1);
+    // CPU cycles since power on
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    // (scanline, dot) the PPU is about to render
+    pub fn ppu_position(&self) -> (u16, usize) {
+        (self.ppu.scanline, self.ppu.dot())
+    }
+
+    // the CPU samples NMI before the last cycle of an instruction, an edge in
+    // the last cycle is only seen at the end of the next one
+    pub fn poll_nmi_status(&mut self) -> bool {
+        match self.nmi_edge {
+            Some(cycle) if cycle < self.cycles => {
+                self.nmi_edge = None;
+                true
+            }
+            Some(_) => {
+                self.nmi_edge = Some(self.cycles);
+                false
+            }
+            None => false,
         }
     }
 
-    pub fn poll_nmi_status(&mut self) -> Option<u8> {
-        self.ppu.poll_nmi_interrupt()
+    // BRK and IRQ look at NMI once more at the end of their 4th cycle, an edge
+    // raised up to and including that cycle takes over their vector
+    pub fn poll_nmi_hijack(&mut self) -> bool {
+        match self.nmi_edge {
+            Some(cycle) if cycle <= self.cycles => {
+                self.nmi_edge = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // what the reset button does outside the CPU, RAM and cartridge RAM survive
+    pub fn reset(&mut self) {
+        self.apu.reset();
+        self.ppu.reset();
+        self.mapper.borrow_mut().reset();
+        self.nmi_edge = None;
+        self.oam_dma_page = None;
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.mapper.borrow_mut().load_save_data(data);
+    }
+
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.mapper.borrow().save_data().map(|data| data.to_vec())
+    }
+
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.ppu.sprite_limit = enabled;
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.ppu.palette = palette;
+    }
+
+    pub fn poll_irq_status(&self) -> bool {
+        self.mapper.borrow().irq() || self.apu.irq()
     }
 }
 
@@ -207,11 +351,109 @@ impl<'a> BUS<'a> {
 mod test {
     use super::*;
     use crate::components::cartridge::test;
+    use std::cell::Cell;
 
     #[test]
     fn test_memory_read_write_to_ram() {
-        let mut bus = BUS::new(test::test_rom(), |_ppu: &PPU, _joypad: &mut Joypad| {});
+        let mut bus = BUS::new(test::test_rom(), |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {});
         bus.memory_write(0x01, 0x55);
         assert_eq!(bus.memory_read(0x01), 0x55);
     }
+
+    #[test]
+    fn test_memory_read_write_to_prg_ram() {
+        let mut bus = BUS::new(test::test_rom(), |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {});
+        bus.memory_write(0x6000, 0x55);
+        bus.memory_write(0x7fff, 0x66);
+        assert_eq!(bus.memory_read(0x6000), 0x55);
+        assert_eq!(bus.memory_read(0x7fff), 0x66);
+    }
+
+    #[test]
+    fn test_apu_status_register() {
+        let mut bus = BUS::new(test::test_rom(), |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {});
+        bus.memory_write(0x4015, 0b0000_0100);
+        bus.memory_write(0x400B, 0b0000_1000);
+        assert_eq!(bus.memory_read(0x4015), 0b0000_0100);
+    }
+
+    #[test]
+    fn test_oam_dma_stalls_cpu() {
+        let mut bus = BUS::new(test::test_rom(), |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {});
+        bus.memory_write(0x0200, 0x66);
+        bus.memory_write(0x4014, 0x02);
+        assert_eq!(bus.cycles, 0);
+        bus.run_oam_dma();
+        assert_eq!(bus.cycles, 513);
+        assert_eq!(bus.ppu.oam_data[0], 0x66);
+
+        bus.memory_write(0x4014, 0x02);
+        bus.run_oam_dma();
+        assert_eq!(bus.cycles, 513 + 514);
+    }
+
+    #[test]
+    fn test_pal_ppu_clock_ratio() {
+        let frames = Cell::new(0);
+        let mut bus = BUS::new(test::test_rom(), |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {
+            frames.set(frames.get() + 1)
+        });
+        bus.set_region(Region::Pal);
+
+        // 341 * 312 dots at 3.2 dots per cycle
+        for _ in 0..33247 {
+            bus.tick(1);
+        }
+        assert_eq!(frames.get(), 0);
+        bus.tick(1);
+        assert_eq!(frames.get(), 1);
+    }
+
+    #[test]
+    fn test_dmc_fetch_stalls_cpu() {
+        // the fetch comes after the first cycle following the $4015 write
+        for (start, stall) in [(0, DMC_DMA_CYCLES_ALIGNED), (1, DMC_DMA_CYCLES)] {
+            let mut bus = BUS::new(test::test_rom(), |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {});
+            bus.tick(start);
+            bus.memory_write(0x4010, 0b1000_0000);
+            bus.memory_write(0x4013, 0x00);
+            bus.memory_write(0x4015, 0b0001_0000);
+
+            bus.tick(1);
+            assert_eq!(bus.cycles, start as usize + 1 + stall as usize);
+            assert!(bus.poll_irq_status());
+        }
+    }
+
+    #[test]
+    fn test_dmc_fetch_during_oam_dma() {
+        let mut bus = BUS::new(test::test_rom(), |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {});
+        bus.memory_write(0x4010, 0b1000_0000);
+        bus.memory_write(0x4013, 0x00);
+        bus.memory_write(0x4015, 0b0001_0000);
+        bus.memory_write(0x4014, 0x02);
+
+        bus.run_oam_dma();
+        assert_eq!(bus.cycles, 513 + DMC_DMA_CYCLES_DURING_OAM_DMA as usize);
+        assert!(bus.poll_irq_status());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut bus = BUS::new(test::test_rom(), |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {});
+        bus.memory_write(0x01, 0x55);
+        bus.memory_write(0x4015, 0b0000_0100);
+        bus.memory_write(0x400B, 0b0000_1000);
+        bus.memory_write(0x2000, 0b1000_0000);
+        bus.memory_write(0x4014, 0x02);
+        bus.nmi_edge = Some(0);
+
+        bus.reset();
+        assert_eq!(bus.memory_read(0x4015), 0);
+        assert!(!bus.ppu.control.generate_vblank_nmi());
+        assert_eq!(bus.nmi_edge, None);
+        bus.run_oam_dma();
+        assert_eq!(bus.cycles, 0);
+        assert_eq!(bus.memory_read(0x01), 0x55);
+    }
 }
@@ -1,3 +1,5 @@
+use super::mappers;
+
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
@@ -7,27 +9,103 @@ pub enum Mirroring {
     Vertical,
     Horizontal,
     FourScreen,
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+/// Cartridge hardware sitting between the console and the ROM chips.
+///
+/// CPU accesses cover $4020-$FFFF and PPU accesses cover the pattern tables
+/// at $0000-$1FFF. Every mapper decides how those land in its PRG/CHR banks.
+pub trait Mapper {
+    fn cpu_read(&mut self, address: u16) -> u8;
+    fn cpu_write(&mut self, address: u16, data: u8);
+
+    fn ppu_read(&mut self, address: u16) -> u8;
+    fn ppu_write(&mut self, address: u16, data: u8);
+
+    fn mirroring(&self) -> Mirroring;
+
+    /// State of the cartridge IRQ line (true = asserted)
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Called by the PPU when address line A12 goes from low to high
+    fn ppu_a12_rising_edge(&mut self) {}
+
+    /// Battery-backed PRG RAM contents, None if the cartridge has no battery
+    fn save_data(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn load_save_data(&mut self, _data: &[u8]) {}
+
+    /// Called when the console is reset, cartridges never see the reset line
+    /// so mappers keep their banks
+    fn reset(&mut self) {}
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
 }
 
 pub struct Rom {
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
-    pub mapper: u8,
+    pub mapper: u16,
+    pub submapper: u8,
     pub screen_mirroring: Mirroring,
+
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub battery: bool,
+
+    pub region: Region,
+    pub expansion_device: u8,
+}
+
+// NES 2.0 ROM sizes: an MSB nibble of $F switches the LSB byte to
+// exponent-multiplier form EEEEEEMM, size = 2^E * (MM*2+1)
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> usize {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * page_size
+    }
+}
+
+// NES 2.0 RAM sizes are shift counts: 0 means none, otherwise 64 << n bytes
+fn nes2_ram_size(shift_count: u8) -> usize {
+    if shift_count == 0 {
+        0
+    } else {
+        64 << shift_count
+    }
 }
 
 impl Rom {
     pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
-        if &raw[0..4] != NES_TAG {
+        if raw.len() < 16 || &raw[0..4] != NES_TAG {
             return Err("File is not in iNES file format".to_string());
         }
 
-        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+        let mut mapper = ((raw[7] & 0b1111_0000) | (raw[6] >> 4)) as u16;
 
         let ines_ver = (raw[7] >> 2) & 0b11;
-        if ines_ver != 0 {
-            return Err("NES2.0 format is not supported".to_string());
-        }
+        let nes2 = match ines_ver {
+            0 => false,
+            2 => true,
+            _ => return Err("Unknown iNES header version".to_string()),
+        };
 
         let four_screen = raw[6] & 0b1000 != 0;
         let vertical_mirroring = raw[6] & 0b1 != 0;
@@ -37,19 +115,84 @@ impl Rom {
             (false, false) => Mirroring::Horizontal,
         };
 
-        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        let prg_rom_size;
+        let chr_rom_size;
+        let submapper;
+        let prg_ram_size;
+        let prg_nvram_size;
+        let chr_ram_size;
+        let chr_nvram_size;
+        let region;
+        let expansion_device;
+
+        if nes2 {
+            mapper |= ((raw[8] & 0b1111) as u16) << 8;
+            submapper = raw[8] >> 4;
+
+            prg_rom_size = nes2_rom_size(raw[4], raw[9] & 0b1111, PRG_ROM_PAGE_SIZE);
+            chr_rom_size = nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE);
+
+            prg_ram_size = nes2_ram_size(raw[10] & 0b1111);
+            prg_nvram_size = nes2_ram_size(raw[10] >> 4);
+            chr_ram_size = nes2_ram_size(raw[11] & 0b1111);
+            chr_nvram_size = nes2_ram_size(raw[11] >> 4);
+
+            region = match raw[12] & 0b11 {
+                0 => Region::Ntsc,
+                1 => Region::Pal,
+                2 => Region::MultiRegion,
+                _ => Region::Dendy,
+            };
+            expansion_device = raw[15] & 0b11_1111;
+        } else {
+            submapper = 0;
+
+            prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+            chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+
+            // byte 8 is PRG RAM in 8K units, 0 infers 8K for compatibility
+            prg_ram_size = (raw[8].max(1) as usize) * 0x2000;
+            prg_nvram_size = 0;
+            chr_ram_size = if chr_rom_size == 0 { 0x2000 } else { 0 };
+            chr_nvram_size = 0;
 
+            region = if raw[9] & 1 == 0 {
+                Region::Ntsc
+            } else {
+                Region::Pal
+            };
+            expansion_device = 0;
+        }
+
+        if !mappers::is_supported(mapper) {
+            return Err(format!("unsupported mapper {}", mapper));
+        }
+
+        let battery = raw[6] & 0b10 != 0;
         let skip_trainer = raw[6] & 0b100 != 0;
 
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
-        let chr_rom_start = prg_rom_start + prg_rom_size;
+        let prg_rom_start: usize = 16 + if skip_trainer { 512 } else { 0 };
+        // exponent sizes can be absurdly large, a malformed header must not overflow
+        let chr_rom_start = prg_rom_start.checked_add(prg_rom_size);
+        let chr_rom_end = chr_rom_start.and_then(|start| start.checked_add(chr_rom_size));
+        let (chr_rom_start, chr_rom_end) = match (chr_rom_start, chr_rom_end) {
+            (Some(start), Some(end)) if end <= raw.len() => (start, end),
+            _ => return Err("File is smaller than the ROM size in its header".to_string()),
+        };
 
         Ok(Rom {
-            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
-            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..chr_rom_end].to_vec(),
             mapper: mapper,
+            submapper,
             screen_mirroring: screen_mirroring,
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size,
+            chr_nvram_size,
+            battery,
+            region,
+            expansion_device,
         })
     }
 }
@@ -58,14 +201,14 @@ pub mod test {
 
     use super::*;
 
-    struct TestRom {
-        header: Vec<u8>,
-        trainer: Option<Vec<u8>>,
-        pgp_rom: Vec<u8>,
-        chr_rom: Vec<u8>,
+    pub struct TestRom {
+        pub header: Vec<u8>,
+        pub trainer: Option<Vec<u8>>,
+        pub pgp_rom: Vec<u8>,
+        pub chr_rom: Vec<u8>,
     }
 
-    fn create_rom(rom: TestRom) -> Vec<u8> {
+    pub fn create_rom(rom: TestRom) -> Vec<u8> {
         let mut result = Vec::with_capacity(
             rom.header.len()
                 + rom.trainer.as_ref().map_or(0, |t| t.len())
@@ -96,6 +239,25 @@ pub mod test {
         Rom::new(&test_rom).unwrap()
     }
 
+    // one bank of `size` bytes per value, filled with that value
+    pub fn banks(values: std::ops::Range<u8>, size: usize) -> Vec<u8> {
+        values.flat_map(|value| vec![value; size]).collect()
+    }
+
+    // NROM with the given 16K or 32K of PRG ROM and 8K of blank CHR ROM
+    pub fn nrom(pgp_rom: Vec<u8>) -> Rom {
+        let pages = (pgp_rom.len() / PRG_ROM_PAGE_SIZE) as u8;
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, pages, 0x01, 0x00, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom,
+            chr_rom: vec![0; CHR_ROM_PAGE_SIZE],
+        });
+        Rom::new(&raw).unwrap()
+    }
+
     #[test]
     fn test() {
         let test_rom = create_rom(TestRom {
@@ -150,19 +312,80 @@ pub mod test {
     }
 
     #[test]
-    fn test_nes2_is_not_supported() {
+    fn test_nes2_header() {
         let test_rom = create_rom(TestRom {
             header: vec![
-                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, 0x8, 00, 00, 00, 00, 00, 00, 00, 00,
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0x12, 0x08, 0x20, 0x00, 0x70, 0x07, 0x01,
+                00, 00, 0x01,
             ],
             trainer: None,
-            pgp_rom: vec![1; 1 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
         });
-        let rom = Rom::new(&test_rom);
-        match rom {
-            Result::Ok(_) => assert!(false, "should not load rom"),
-            Result::Err(str) => assert_eq!(str, "NES2.0 format is not supported"),
-        }
+
+        let rom: Rom = Rom::new(&test_rom).unwrap();
+
+        assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
+        assert_eq!(rom.chr_rom, vec![]);
+        assert_eq!(rom.mapper, 1);
+        assert_eq!(rom.submapper, 2);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 8192);
+        assert_eq!(rom.chr_ram_size, 8192);
+        assert_eq!(rom.chr_nvram_size, 0);
+        assert_eq!(rom.region, Region::Pal);
+        assert_eq!(rom.expansion_device, 1);
+    }
+
+    #[test]
+    fn test_nes2_extended_mapper_and_exponent_size() {
+        assert_eq!(nes2_rom_size(0b0001_0101, 0x0F, PRG_ROM_PAGE_SIZE), 32 * 3);
+        assert_eq!(nes2_rom_size(0x02, 0x01, PRG_ROM_PAGE_SIZE), 0x102 * PRG_ROM_PAGE_SIZE);
+
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x08, 0x01, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        assert_eq!(
+            Rom::new(&test_rom).err(),
+            Some("unsupported mapper 256".to_string())
+        );
+    }
+
+    #[test]
+    fn test_nes2_oversized_rom() {
+        // exponent form with the largest exponent saturates to usize::MAX
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0xFF, 0x01, 0x00, 0x08, 00, 0x0F, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        assert_eq!(
+            Rom::new(&test_rom).err(),
+            Some("File is smaller than the ROM size in its header".to_string())
+        );
+    }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0xF1, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        assert_eq!(
+            Rom::new(&test_rom).err(),
+            Some("unsupported mapper 15".to_string())
+        );
     }
 }
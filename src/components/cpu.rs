@@ -37,6 +37,8 @@ pub struct CPU<'a> {
     pub register_pc: u16,
     pub register_sp: u8,
     pub bus: BUS<'a>,
+    // NMI seen at the end of the last instruction, serviced before the next one
+    nmi_pending: bool,
 }
 
 #[derive(Debug)]
@@ -60,21 +62,34 @@ fn page_cross(addr1: u16, addr2: u16) -> bool {
 #[derive(PartialEq, Eq)]
 pub enum InterruptType {
     NMI,
+    IRQ,
+    BRK,
 }
 
 #[derive(PartialEq, Eq)]
 pub struct Interrupt {
     pub interrupt_type: InterruptType,
     pub vector_address: u16,
+    // B and unused bits of the pushed status
     pub binary_flag_mask: u8,
-    pub cpu_cycles: u8,
 }
 
 pub const NMI: Interrupt = Interrupt {
     interrupt_type: InterruptType::NMI,
-    vector_address: 0xfffA,
+    vector_address: 0xfffa,
     binary_flag_mask: 0b00100000,
-    cpu_cycles: 2,
+};
+
+pub const IRQ: Interrupt = Interrupt {
+    interrupt_type: InterruptType::IRQ,
+    vector_address: 0xfffe,
+    binary_flag_mask: 0b00100000,
+};
+
+pub const BRK: Interrupt = Interrupt {
+    interrupt_type: InterruptType::BRK,
+    vector_address: 0xfffe,
+    binary_flag_mask: 0b00110000,
 };
 
 impl<'a> CPU<'a> {
@@ -87,6 +102,7 @@ impl<'a> CPU<'a> {
             register_pc: 0,
             register_p: CpuFlags::from_bits_truncate(0b100100),
             bus,
+            nmi_pending: false,
         }
     }
 
@@ -352,7 +368,11 @@ impl<'a> CPU<'a> {
         self.branch(!self.register_p.contains(CpuFlags::NEGATIVE));
     }
 
-    // BRK is a simple return in Assembler interpreter function
+    pub fn brk(&mut self) {
+        // the byte after BRK is padding, RTI returns past it
+        self.register_pc = self.register_pc.wrapping_add(1);
+        self.interrupt(BRK);
+    }
 
     pub fn bvc(&mut self) {
         self.branch(!self.register_p.contains(CpuFlags::OVERFLOW));
@@ -969,17 +989,33 @@ impl<'a> CPU<'a> {
         }
     }
 
+    // 7 cycles, the same sequence for BRK, IRQ and NMI:
+    // 1-2  read the next opcode and throw it away (BRK: opcode and padding byte)
+    // 3-4  push PCH, PCL
+    // 5    push P, an NMI raised in cycles 1-4 takes over the vector (hijacking),
+    //      one raised from here on is taken after the interrupt instead
+    // 6-7  fetch the vector
     pub fn interrupt(&mut self, interrupt: Interrupt) {
+        self.bus.tick(2);
+
         self.stack_push_u16(self.register_pc);
-        let mut flag = self.register_p.clone();
-        flag.set(CpuFlags::BREAK, interrupt.binary_flag_mask & 0b010000 == 1);
-        flag.set(CpuFlags::UNUSED, interrupt.binary_flag_mask & 0b100000 == 1);
+        self.bus.tick(2);
+
+        let vector_address = match interrupt.interrupt_type {
+            InterruptType::NMI => interrupt.vector_address,
+            _ if self.bus.poll_nmi_hijack() => NMI.vector_address,
+            _ => interrupt.vector_address,
+        };
 
+        let mut flag = self.register_p;
+        flag.set(CpuFlags::BREAK, interrupt.binary_flag_mask & 0b010000 != 0);
+        flag.set(CpuFlags::UNUSED, interrupt.binary_flag_mask & 0b100000 != 0);
         self.stack_push(flag.bits);
         self.register_p.insert(CpuFlags::INTERRUPT_DISABLE);
+        self.bus.tick(1);
 
-        self.bus.tick(interrupt.cpu_cycles);
-        self.register_pc = self.memory_read_u16(interrupt.vector_address);
+        self.register_pc = self.memory_read_u16(vector_address);
+        self.bus.tick(2);
     }
 
     pub fn load_and_run(&mut self, program: Vec<u8>) {
@@ -1001,8 +1037,11 @@ impl<'a> CPU<'a> {
         self.register_y = 0;
         self.register_sp = STACK_RESET;
         self.register_p = CpuFlags::from_bits_truncate(0b100100);
+        self.nmi_pending = false;
 
         self.register_pc = self.memory_read_u16(0xFFFC);
+        // the reset sequence takes 7 cycles before the first instruction
+        self.bus.tick(7);
     }
 
     pub fn run(&mut self) {
@@ -1014,22 +1053,223 @@ impl<'a> CPU<'a> {
         F: FnMut(&mut CPU),
     {
         let assembler = Assembler::new();
+        while !self.step(&assembler, &mut callback) {}
+    }
+
+    // services a pending interrupt, hands the CPU to the callback right before the
+    // next instruction and executes it. Returns true once the CPU is halted by a
+    // JAM opcode
+    pub fn step<F>(&mut self, assembler: &Assembler, callback: &mut F) -> bool
+    where
+        F: FnMut(&mut CPU),
+    {
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.interrupt(NMI);
+        } else if self.bus.poll_irq_status()
+            && !self.register_p.contains(CpuFlags::INTERRUPT_DISABLE)
+        {
+            self.interrupt(IRQ);
+        }
+
+        callback(self);
+
+        let code = self.memory_read(self.register_pc);
+        self.register_pc += 1;
+
+        let halted = assembler.interpret(self, code);
+        self.bus.run_oam_dma();
+
+        // NMI is edge triggered, the edge stays latched until it is serviced
+        self.nmi_pending |= self.bus.poll_nmi_status();
+        halted
+    }
+}
 
-        loop {
-            if let Some(_nmi) = self.bus.poll_nmi_status() {
-                self.interrupt(NMI);
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::components::apu::APU;
+    use crate::components::cartridge::test::{nrom, test_rom};
+    use crate::components::joypads::Joypad;
+    use crate::components::ppu::PPU;
+
+    // test_rom's PRG is filled with 1s, so every vector points to $0101
+    fn test_cpu<'a>() -> CPU<'a> {
+        let bus = BUS::new(test_rom(), |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {});
+        CPU::new(bus)
+    }
+
+    // NMI vector at $0300, IRQ/BRK vector at $0400
+    fn vector_cpu<'a>() -> CPU<'a> {
+        let mut pgp_rom = vec![0; 0x8000];
+        pgp_rom[0x7ffa..0x7ffc].copy_from_slice(&[0x00, 0x03]);
+        pgp_rom[0x7ffe..].copy_from_slice(&[0x00, 0x04]);
+
+        let bus = BUS::new(
+            nrom(pgp_rom),
+            |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {},
+        );
+        CPU::new(bus)
+    }
+
+    // CPU cycle in which the first vblank starts (dot 1 of scanline 241)
+    fn vblank_nmi_cycle() -> usize {
+        let mut cpu = vector_cpu();
+        while cpu.bus.ppu_position() < (241, 2) {
+            cpu.bus.tick(1);
+        }
+        cpu.bus.cycles()
+    }
+
+    // PCs of the instructions executed until the CPU halts
+    fn run_trace(cpu: &mut CPU) -> Vec<u16> {
+        let mut trace = vec![];
+        cpu.run_with_callback(|cpu| trace.push(cpu.register_pc));
+        trace
+    }
+
+    #[test]
+    fn test_oam_dma_aligns_after_write() {
+        // STA $4014,X takes 5 cycles, the parity is taken after the last one
+        for (start, dma) in [(0, 514), (1, 513)] {
+            let mut cpu = test_cpu();
+            cpu.bus.tick(start);
+            cpu.load(vec![0x9d, 0x14, 0x40, 0x02]);
+            cpu.register_pc = 0x0600;
+            cpu.run();
+            assert_eq!(cpu.bus.cycles(), start as usize + 5 + dma);
+        }
+    }
+
+    #[test]
+    fn test_interrupt_pushed_flags() {
+        let mut cpu = vector_cpu();
+        cpu.register_pc = 0x0600;
+        cpu.register_p = CpuFlags::from_bits_truncate(0b1100_0011);
+
+        cpu.interrupt(IRQ);
+        assert_eq!(cpu.register_pc, 0x0400);
+        assert_eq!(cpu.memory_read(0x01fb), 0b1110_0011);
+        assert_eq!(cpu.bus.cycles(), 7);
+
+        cpu.interrupt(NMI);
+        assert_eq!(cpu.register_pc, 0x0300);
+        // I was set by the IRQ, B stays clear
+        assert_eq!(cpu.memory_read(0x01f8), 0b1110_0111);
+        assert_eq!(cpu.bus.cycles(), 14);
+    }
+
+    #[test]
+    fn test_nmi_edge_in_last_cycle_is_delayed() {
+        let edge = vblank_nmi_cycle();
+        // the edge comes in the first or the second (last) cycle of the 11th NOP
+        for (offset, nops) in [(21, 11), (22, 12)] {
+            let mut cpu = vector_cpu();
+            cpu.memory_write(0x2000, 0x80);
+            while cpu.bus.cycles() < edge - offset {
+                cpu.bus.tick(1);
             }
 
-            let code = self.memory_read(self.register_pc);
-            self.register_pc += 1;
+            cpu.load(vec![0xea; 16]);
+            cpu.memory_write(0x0300, 0x02);
+            cpu.register_pc = 0x0600;
+
+            let trace = run_trace(&mut cpu);
+            assert_eq!(trace.len(), nops + 1);
+            assert_eq!(trace.last(), Some(&0x0300));
+        }
+    }
 
-            let program_ends = assembler.interpret(self, code);
+    #[test]
+    fn test_nmi_enabled_in_vblank_is_delayed() {
+        let edge = vblank_nmi_cycle();
+        let mut cpu = vector_cpu();
+        while cpu.bus.cycles() <= edge {
+            cpu.bus.tick(1);
+        }
 
-            if program_ends {
-                break;
-            } else {
-                callback(self);
+        // LDA #$80, STA $2000, INX, INX, JAM in the handler
+        cpu.load(vec![0xa9, 0x80, 0x8d, 0x00, 0x20, 0xe8, 0xe8]);
+        cpu.memory_write(0x0300, 0x02);
+        cpu.register_pc = 0x0600;
+
+        // the write lands in the last cycle of STA, one more instruction runs
+        assert_eq!(run_trace(&mut cpu), vec![0x0600, 0x0602, 0x0605, 0x0300]);
+    }
+
+    #[test]
+    fn test_nmi_hijacks_brk() {
+        let edge = vblank_nmi_cycle();
+        let mut cpu = vector_cpu();
+        cpu.memory_write(0x2000, 0x80);
+        // the edge comes in the second cycle of BRK
+        while cpu.bus.cycles() < edge - 2 {
+            cpu.bus.tick(1);
+        }
+
+        cpu.load(vec![0x00, 0xff]);
+        cpu.memory_write(0x0300, 0x02);
+        cpu.register_pc = 0x0600;
+
+        assert_eq!(run_trace(&mut cpu), vec![0x0600, 0x0300]);
+        // a single interrupt frame, still pushed as a BRK
+        assert_eq!(cpu.register_sp, STACK_RESET - 3);
+        assert_eq!(cpu.memory_read(0x01fb) & 0b0011_0000, 0b0011_0000);
+        assert_eq!(cpu.memory_read(0x01fc), 0x02);
+        assert_eq!(cpu.memory_read(0x01fd), 0x06);
+    }
+
+    #[test]
+    fn test_nmi_hijack_cut_off() {
+        let edge = vblank_nmi_cycle();
+        // (cycle of BRK the edge comes in, stack pointer after the NMI handler starts)
+        for (cycle, sp) in [(4, STACK_RESET - 3), (5, STACK_RESET - 6)] {
+            let mut cpu = vector_cpu();
+            cpu.memory_write(0x2000, 0x80);
+            while cpu.bus.cycles() < edge - cycle {
+                cpu.bus.tick(1);
             }
+
+            cpu.load(vec![0x00, 0xff]);
+            cpu.memory_write(0x0300, 0x02);
+            cpu.register_pc = 0x0600;
+
+            assert_eq!(run_trace(&mut cpu), vec![0x0600, 0x0300]);
+            assert_eq!(cpu.register_sp, sp);
         }
     }
+
+    #[test]
+    fn test_brk_software_interrupt() {
+        let mut cpu = test_cpu();
+        cpu.load(vec![0x00, 0xff, 0x02]); // BRK, padding, JAM
+        cpu.memory_write(0x0101, 0x40); // RTI
+        cpu.register_pc = 0x0600;
+        cpu.register_p = CpuFlags::from_bits_truncate(0b1010_0001);
+
+        let assembler = Assembler::new();
+        cpu.step(&assembler, &mut |_| {});
+        assert_eq!(cpu.register_pc, 0x0101);
+        assert_eq!(cpu.register_sp, STACK_RESET - 3);
+        assert_eq!(cpu.memory_read(0x01fd), 0x06);
+        assert_eq!(cpu.memory_read(0x01fc), 0x02);
+        assert_eq!(cpu.memory_read(0x01fb), 0b1011_0001);
+        assert!(cpu.register_p.contains(CpuFlags::INTERRUPT_DISABLE));
+        assert_eq!(cpu.bus.cycles(), 7);
+
+        cpu.step(&assembler, &mut |_| {});
+        assert_eq!(cpu.register_pc, 0x0602);
+        assert_eq!(cpu.register_p.bits(), 0b1010_0001);
+    }
+
+    #[test]
+    fn test_jam_halts() {
+        let mut cpu = test_cpu();
+        cpu.memory_write(0x0101, 0x40); // RTI
+        // INX, BRK, padding, INX, JAM
+        cpu.load_and_run(vec![0xe8, 0x00, 0xff, 0xe8, 0x02]);
+        assert_eq!(cpu.register_pc, 0x0604);
+        assert_eq!(cpu.register_x, 2);
+    }
 }
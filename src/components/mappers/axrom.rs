@@ -0,0 +1,103 @@
+use super::{Chr, PrgRam};
+use crate::components::cartridge::{Mapper, Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+// AxROM (mapper 7): switchable 32K PRG bank and one-screen mirroring
+//
+// 7  bit  0
+// ---- ----
+// xxxM xPPP
+//    |  |||
+//    |  +++- Select 32 KB PRG ROM bank for CPU $8000-$FFFF
+//    +------ Select 1 KB VRAM page for all 4 nametables
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_ram: PrgRam,
+    register: u8,
+}
+
+impl Axrom {
+    pub fn new(rom: Rom) -> Self {
+        Axrom {
+            prg_rom: rom.prg_rom,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            prg_ram: PrgRam::new(rom.prg_ram_size + rom.prg_nvram_size, rom.battery),
+            register: 0,
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram.read(address),
+            0x8000..=0xFFFF => {
+                let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+                let bank = (self.register & 0b111) as usize % banks;
+                self.prg_rom[bank * PRG_BANK_SIZE + (address - 0x8000) as usize]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram.write(address, data),
+            0x8000..=0xFFFF => self.register = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.chr.write(address as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.register & 0b1_0000 == 0 {
+            Mirroring::SingleScreenLower
+        } else {
+            Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        self.prg_ram.save_data()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::components::cartridge::test::{banks, create_rom, TestRom};
+
+    #[test]
+    fn test_prg_banking_and_mirroring() {
+        let pgp_rom = banks(0..4, PRG_BANK_SIZE);
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x08, 0x01, 0x70, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom,
+            chr_rom: vec![0; 0x2000],
+        });
+        let mut mapper = Axrom::new(Rom::new(&raw).unwrap());
+
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+
+        mapper.cpu_write(0x8000, 0b1_0010);
+        assert_eq!(mapper.cpu_read(0xFFFF), 2);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+}
@@ -0,0 +1,99 @@
+use super::{Chr, PrgRam};
+use crate::components::cartridge::{Mapper, Mirroring, Rom};
+
+const CHR_BANK_SIZE: usize = 0x2000;
+
+// CNROM (mapper 3): fixed PRG like NROM, switchable 8K CHR bank
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_ram: PrgRam,
+    mirroring: Mirroring,
+    chr_bank: u8,
+}
+
+impl Cnrom {
+    pub fn new(rom: Rom) -> Self {
+        Cnrom {
+            prg_rom: rom.prg_rom,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            prg_ram: PrgRam::new(rom.prg_ram_size + rom.prg_nvram_size, rom.battery),
+            mirroring: rom.screen_mirroring,
+            chr_bank: 0,
+        }
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let banks = (self.chr.size() / CHR_BANK_SIZE).max(1);
+        let bank = self.chr_bank as usize % banks;
+        bank * CHR_BANK_SIZE + address as usize
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram.read(address),
+            0x8000..=0xFFFF => {
+                let address = (address - 0x8000) as usize % self.prg_rom.len();
+                self.prg_rom[address]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram.write(address, data),
+            0x8000..=0xFFFF => self.chr_bank = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_offset(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.chr.write(self.chr_offset(address), data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        self.prg_ram.save_data()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::components::cartridge::test::{banks, create_rom, TestRom};
+
+    #[test]
+    fn test_chr_banking() {
+        let chr_rom = banks(0..4, CHR_BANK_SIZE);
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x04, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; 0x4000],
+            chr_rom,
+        });
+        let mut mapper = Cnrom::new(Rom::new(&raw).unwrap());
+
+        assert_eq!(mapper.ppu_read(0x0000), 0);
+        mapper.cpu_write(0x8000, 2);
+        assert_eq!(mapper.ppu_read(0x1FFF), 2);
+
+        // 16K PRG is mirrored into $C000-$FFFF
+        assert_eq!(mapper.cpu_read(0xC000), 1);
+    }
+}
@@ -0,0 +1,108 @@
+use super::{Chr, PrgRam};
+use crate::components::cartridge::{Mapper, Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+// GxROM (mapper 66): switchable 32K PRG and 8K CHR banks
+//
+// 7  bit  0
+// ---- ----
+// xxPP xxCC
+//   ||   ||
+//   ||   ++- Select 8 KB CHR ROM bank for PPU $0000-$1FFF
+//   ++------ Select 32 KB PRG ROM bank for CPU $8000-$FFFF
+pub struct Gxrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_ram: PrgRam,
+    mirroring: Mirroring,
+    register: u8,
+}
+
+impl Gxrom {
+    pub fn new(rom: Rom) -> Self {
+        Gxrom {
+            prg_rom: rom.prg_rom,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            prg_ram: PrgRam::new(rom.prg_ram_size + rom.prg_nvram_size, rom.battery),
+            mirroring: rom.screen_mirroring,
+            register: 0,
+        }
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let banks = (self.chr.size() / CHR_BANK_SIZE).max(1);
+        let bank = (self.register & 0b11) as usize % banks;
+        bank * CHR_BANK_SIZE + address as usize
+    }
+}
+
+impl Mapper for Gxrom {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram.read(address),
+            0x8000..=0xFFFF => {
+                let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+                let bank = ((self.register >> 4) & 0b11) as usize % banks;
+                self.prg_rom[bank * PRG_BANK_SIZE + (address - 0x8000) as usize]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram.write(address, data),
+            0x8000..=0xFFFF => self.register = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_offset(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.chr.write(self.chr_offset(address), data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        self.prg_ram.save_data()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::components::cartridge::test::{banks, create_rom, TestRom};
+
+    #[test]
+    fn test_prg_and_chr_banking() {
+        let pgp_rom = banks(0..4, PRG_BANK_SIZE);
+        let chr_rom = banks(10..14, CHR_BANK_SIZE);
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x08, 0x04, 0x20, 0x40, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom,
+            chr_rom,
+        });
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 66);
+
+        let mut mapper = Gxrom::new(rom);
+        mapper.cpu_write(0x8000, 0b0010_0011);
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+        assert_eq!(mapper.ppu_read(0x0000), 13);
+    }
+}
@@ -0,0 +1,255 @@
+use super::{Chr, PrgRam};
+use crate::components::cartridge::{Mapper, Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+// MMC1 (mapper 1): registers are loaded serially through a 5 bit shift register
+//
+// Control ($8000-$9FFF)
+// 4bit0
+// -----
+// CPPMM
+// |||||
+// |||++- Mirroring (0: one-screen, lower bank; 1: one-screen, upper bank;
+// |||               2: vertical; 3: horizontal)
+// |++--- PRG ROM bank mode (0, 1: switch 32 KB at $8000, ignoring low bit of bank number;
+// |                         2: fix first bank at $8000 and switch 16 KB bank at $C000;
+// |                         3: fix last bank at $C000 and switch 16 KB bank at $8000)
+// +----- CHR ROM bank mode (0: switch 8 KB at a time; 1: switch two separate 4 KB banks)
+//
+// CHR bank 0 ($A000-$BFFF), CHR bank 1 ($C000-$DFFF), PRG bank ($E000-$FFFF)
+// PRG RAM at $6000-$7FFF is disabled by bit 4 of the PRG bank register
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_ram: PrgRam,
+
+    shift_register: u8,
+    shift_count: u8,
+
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    pub fn new(rom: Rom) -> Self {
+        Mmc1 {
+            prg_rom: rom.prg_rom,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            prg_ram: PrgRam::new(rom.prg_ram_size + rom.prg_nvram_size, rom.battery),
+            shift_register: 0,
+            shift_count: 0,
+            control: 0x0C,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank_0 = value,
+            0xC000..=0xDFFF => self.chr_bank_1 = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    // bit 4 of the PRG bank register: 0 = PRG RAM enabled
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0b1_0000 == 0
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let bank = self.prg_bank as usize & 0x0F;
+        let upper_half = address >= 0xC000;
+
+        let bank = match ((self.control >> 2) & 0b11, upper_half) {
+            (0 | 1, false) => bank & !1,
+            (0 | 1, true) => bank | 1,
+            (2, false) => 0,
+            (2, true) => bank,
+            (_, false) => bank,
+            (_, true) => banks - 1,
+        };
+
+        (bank % banks) * PRG_BANK_SIZE + (address as usize & 0x3FFF)
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let banks = (self.chr.size() / CHR_BANK_SIZE).max(1);
+        let upper_half = address >= 0x1000;
+
+        let bank = match (self.control & 0b1_0000 != 0, upper_half) {
+            (false, false) => self.chr_bank_0 & !1,
+            (false, true) => self.chr_bank_0 | 1,
+            (true, false) => self.chr_bank_0,
+            (true, true) => self.chr_bank_1,
+        };
+
+        (bank as usize % banks) * CHR_BANK_SIZE + (address as usize & 0x0FFF)
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram.read(address),
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        if address < 0x8000 {
+            if let (0x6000..=0x7FFF, true) = (address, self.prg_ram_enabled()) {
+                self.prg_ram.write(address, data);
+            }
+            return;
+        }
+
+        if data & 0x80 != 0 {
+            self.shift_register = 0;
+            self.shift_count = 0;
+            self.control |= 0x0C;
+            return;
+        }
+
+        self.shift_register |= (data & 1) << self.shift_count;
+        self.shift_count += 1;
+
+        if self.shift_count == 5 {
+            self.write_register(address, self.shift_register);
+            self.shift_register = 0;
+            self.shift_count = 0;
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_offset(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.chr.write(self.chr_offset(address), data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        self.prg_ram.save_data()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::components::cartridge::test::{banks, create_rom, TestRom};
+
+    fn mmc1_rom() -> Mmc1 {
+        let pgp_rom = banks(0..8, PRG_BANK_SIZE);
+        let chr_rom = banks(0..8, CHR_BANK_SIZE);
+
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x08, 0x04, 0x12, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom,
+            chr_rom,
+        });
+
+        Mmc1::new(Rom::new(&raw).unwrap())
+    }
+
+    fn serial_write(mapper: &mut Mmc1, address: u16, value: u8) {
+        for i in 0..5 {
+            mapper.cpu_write(address, (value >> i) & 1);
+        }
+    }
+
+    #[test]
+    fn test_power_on_fixes_last_bank() {
+        let mut mapper = mmc1_rom();
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 7);
+    }
+
+    #[test]
+    fn test_prg_16k_switching() {
+        let mut mapper = mmc1_rom();
+        serial_write(&mut mapper, 0xE000, 5);
+        assert_eq!(mapper.cpu_read(0x8000), 5);
+        assert_eq!(mapper.cpu_read(0xFFFF), 7);
+
+        serial_write(&mut mapper, 0x8000, 0b01000); // fix first bank
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 5);
+    }
+
+    #[test]
+    fn test_prg_32k_switching() {
+        let mut mapper = mmc1_rom();
+        serial_write(&mut mapper, 0x8000, 0b00000);
+        serial_write(&mut mapper, 0xE000, 5);
+        assert_eq!(mapper.cpu_read(0x8000), 4);
+        assert_eq!(mapper.cpu_read(0xC000), 5);
+    }
+
+    #[test]
+    fn test_chr_banking() {
+        let mut mapper = mmc1_rom();
+        serial_write(&mut mapper, 0xA000, 3);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+        assert_eq!(mapper.ppu_read(0x1000), 3);
+
+        serial_write(&mut mapper, 0x8000, 0b10000); // two 4K banks
+        serial_write(&mut mapper, 0xC000, 6);
+        assert_eq!(mapper.ppu_read(0x0000), 3);
+        assert_eq!(mapper.ppu_read(0x1000), 6);
+    }
+
+    #[test]
+    fn test_reset_and_mirroring() {
+        let mut mapper = mmc1_rom();
+        mapper.cpu_write(0x8000, 1);
+        mapper.cpu_write(0x8000, 0x80); // reset discards partial write
+        serial_write(&mut mapper, 0x8000, 0b01110);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+
+        serial_write(&mut mapper, 0x8000, 0b01101);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_prg_ram_and_battery() {
+        let mut mapper = mmc1_rom();
+        mapper.cpu_write(0x6000, 0x55);
+        assert_eq!(mapper.cpu_read(0x6000), 0x55);
+        assert_eq!(mapper.save_data().unwrap()[0], 0x55);
+
+        serial_write(&mut mapper, 0xE000, 0b10000); // disable PRG RAM
+        mapper.cpu_write(0x6000, 0x66);
+        assert_eq!(mapper.cpu_read(0x6000), 0);
+
+        serial_write(&mut mapper, 0xE000, 0);
+        assert_eq!(mapper.cpu_read(0x6000), 0x55);
+
+        mapper.load_save_data(&[0x77; 0x2000]);
+        assert_eq!(mapper.cpu_read(0x7FFF), 0x77);
+    }
+}
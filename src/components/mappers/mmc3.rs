@@ -0,0 +1,259 @@
+use super::{Chr, PrgRam};
+use crate::components::cartridge::{Mapper, Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+// MMC3 (mapper 4): 8K PRG banks, 1K/2K CHR banks and a scanline counter
+// clocked by rising edges of PPU A12
+//
+// Bank select ($8000-$9FFE, even)
+// 7  bit  0
+// ---- ----
+// CPMx xRRR
+// |||   |||
+// |||   +++- Bank register to update on next write to Bank data register
+// |||        (0-1: 2 KB CHR banks, 2-5: 1 KB CHR banks, 6-7: 8 KB PRG banks)
+// ||+------- Nothing on the MMC3
+// |+-------- PRG ROM bank mode (0: $8000-$9FFF swappable, $C000-$DFFF fixed
+// |                             1: $C000-$DFFF swappable, $8000-$9FFF fixed)
+// +--------- CHR A12 inversion (0: two 2 KB banks at $0000-$0FFF,
+//                                  four 1 KB banks at $1000-$1FFF;
+//                               1: two 2 KB banks at $1000-$1FFF,
+//                                  four 1 KB banks at $0000-$0FFF)
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_ram: PrgRam,
+    four_screen: bool,
+
+    bank_select: u8,
+    registers: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_protect: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Mmc3 {
+    pub fn new(rom: Rom) -> Self {
+        Mmc3 {
+            prg_rom: rom.prg_rom,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            prg_ram: PrgRam::new(rom.prg_ram_size + rom.prg_nvram_size, rom.battery),
+            four_screen: rom.screen_mirroring == Mirroring::FourScreen,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: rom.screen_mirroring,
+            prg_ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let second_last = banks.saturating_sub(2);
+        let prg_mode = self.bank_select & 0b0100_0000 != 0;
+
+        let bank = match (address, prg_mode) {
+            (0x8000..=0x9FFF, false) => self.registers[6] as usize,
+            (0x8000..=0x9FFF, true) => second_last,
+            (0xA000..=0xBFFF, _) => self.registers[7] as usize,
+            (0xC000..=0xDFFF, false) => second_last,
+            (0xC000..=0xDFFF, true) => self.registers[6] as usize,
+            _ => banks - 1,
+        };
+
+        (bank % banks) * PRG_BANK_SIZE + (address as usize & 0x1FFF)
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let banks = (self.chr.size() / CHR_BANK_SIZE).max(1);
+        let inverted = self.bank_select & 0b1000_0000 != 0;
+        let address = if inverted { address ^ 0x1000 } else { address };
+
+        let bank = match address {
+            0x0000..=0x03FF => self.registers[0] & !1,
+            0x0400..=0x07FF => self.registers[0] | 1,
+            0x0800..=0x0BFF => self.registers[1] & !1,
+            0x0C00..=0x0FFF => self.registers[1] | 1,
+            0x1000..=0x13FF => self.registers[2],
+            0x1400..=0x17FF => self.registers[3],
+            0x1800..=0x1BFF => self.registers[4],
+            _ => self.registers[5],
+        };
+
+        (bank as usize % banks) * CHR_BANK_SIZE + (address as usize & 0x03FF)
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_protect & 0x80 != 0 => self.prg_ram.read(address),
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        let even = address & 1 == 0;
+        match (address, even) {
+            // PRG RAM protect bit 7: RAM chip enable, bit 6: deny writes
+            (0x6000..=0x7FFF, _) if self.prg_ram_protect & 0b1100_0000 == 0b1000_0000 => {
+                self.prg_ram.write(address, data);
+            }
+            (0x8000..=0x9FFF, true) => self.bank_select = data,
+            (0x8000..=0x9FFF, false) => {
+                self.registers[(self.bank_select & 0b111) as usize] = data;
+            }
+            (0xA000..=0xBFFF, true) if !self.four_screen => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            (0xA000..=0xBFFF, false) => self.prg_ram_protect = data,
+            (0xC000..=0xDFFF, true) => self.irq_latch = data,
+            (0xC000..=0xDFFF, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xE000..=0xFFFF, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (0xE000..=0xFFFF, false) => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_offset(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.chr.write(self.chr_offset(address), data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn ppu_a12_rising_edge(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        self.prg_ram.save_data()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::components::cartridge::test::{banks, create_rom, TestRom};
+
+    fn mmc3_rom() -> Mmc3 {
+        let pgp_rom = banks(0..16, PRG_BANK_SIZE);
+        let chr_rom = banks(0..32, CHR_BANK_SIZE);
+
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x08, 0x04, 0x40, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom,
+            chr_rom,
+        });
+
+        Mmc3::new(Rom::new(&raw).unwrap())
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut mapper = mmc3_rom();
+        mapper.cpu_write(0x8000, 6);
+        mapper.cpu_write(0x8001, 3);
+        mapper.cpu_write(0x8000, 7);
+        mapper.cpu_write(0x8001, 9);
+
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xA000), 9);
+        assert_eq!(mapper.cpu_read(0xC000), 14);
+        assert_eq!(mapper.cpu_read(0xE000), 15);
+
+        mapper.cpu_write(0x8000, 0b0100_0000);
+        assert_eq!(mapper.cpu_read(0x8000), 14);
+        assert_eq!(mapper.cpu_read(0xC000), 3);
+    }
+
+    #[test]
+    fn test_chr_banking() {
+        let mut mapper = mmc3_rom();
+        mapper.cpu_write(0x8000, 0);
+        mapper.cpu_write(0x8001, 5);
+        mapper.cpu_write(0x8000, 2);
+        mapper.cpu_write(0x8001, 20);
+
+        assert_eq!(mapper.ppu_read(0x0000), 4);
+        assert_eq!(mapper.ppu_read(0x0400), 5);
+        assert_eq!(mapper.ppu_read(0x1000), 20);
+
+        mapper.cpu_write(0x8000, 0b1000_0000);
+        assert_eq!(mapper.ppu_read(0x1000), 4);
+        assert_eq!(mapper.ppu_read(0x0000), 20);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = mmc3_rom();
+        mapper.cpu_write(0xA000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        mapper.cpu_write(0xA000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mapper = mmc3_rom();
+        mapper.cpu_write(0xC000, 2);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+
+        mapper.ppu_a12_rising_edge(); // reload to 2
+        mapper.ppu_a12_rising_edge(); // 1
+        assert!(!mapper.irq());
+        mapper.ppu_a12_rising_edge(); // 0
+        assert!(mapper.irq());
+
+        mapper.cpu_write(0xE000, 0);
+        assert!(!mapper.irq());
+    }
+}
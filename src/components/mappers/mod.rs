@@ -0,0 +1,119 @@
+pub mod axrom;
+pub mod cnrom;
+pub mod gxrom;
+pub mod mmc1;
+pub mod mmc3;
+pub mod nrom;
+pub mod uxrom;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::cartridge::{Mapper, Rom};
+use axrom::Axrom;
+use cnrom::Cnrom;
+use gxrom::Gxrom;
+use mmc1::Mmc1;
+use mmc3::Mmc3;
+use nrom::Nrom;
+use uxrom::Uxrom;
+
+/// Pattern table memory of a cartridge: CHR ROM, or writable CHR RAM when
+/// the header declares no CHR ROM banks
+pub struct Chr {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl Chr {
+    pub fn new(chr_rom: Vec<u8>, chr_ram_size: usize) -> Self {
+        if chr_rom.is_empty() {
+            Chr {
+                data: vec![0; chr_ram_size.max(0x2000)],
+                writable: true,
+            }
+        } else {
+            Chr {
+                data: chr_rom,
+                writable: false,
+            }
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn read(&self, offset: usize) -> u8 {
+        self.data[offset % self.data.len()]
+    }
+
+    pub fn write(&mut self, offset: usize, data: u8) {
+        if self.writable {
+            let len = self.data.len();
+            self.data[offset % len] = data;
+        } else {
+            println!("attempt to write to chr rom space {}", offset);
+        }
+    }
+}
+
+/// Work RAM mapped at $6000-$7FFF, optionally kept alive by a battery
+pub struct PrgRam {
+    data: Vec<u8>,
+    battery: bool,
+}
+
+impl PrgRam {
+    pub fn new(size: usize, battery: bool) -> Self {
+        PrgRam {
+            data: vec![0; size],
+            battery,
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        if self.data.is_empty() {
+            return 0;
+        }
+        self.data[(address - 0x6000) as usize % self.data.len()]
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        if self.data.is_empty() {
+            return;
+        }
+        let len = self.data.len();
+        self.data[(address - 0x6000) as usize % len] = data;
+    }
+
+    pub fn save_data(&self) -> Option<&[u8]> {
+        if self.battery && !self.data.is_empty() {
+            Some(&self.data)
+        } else {
+            None
+        }
+    }
+
+    pub fn load(&mut self, data: &[u8]) {
+        let len = self.data.len().min(data.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+pub fn is_supported(mapper: u16) -> bool {
+    matches!(mapper, 0 | 1 | 2 | 3 | 4 | 7 | 66)
+}
+
+pub fn new_mapper(rom: Rom) -> Rc<RefCell<dyn Mapper>> {
+    match rom.mapper {
+        0 => Rc::new(RefCell::new(Nrom::new(rom))),
+        1 => Rc::new(RefCell::new(Mmc1::new(rom))),
+        2 => Rc::new(RefCell::new(Uxrom::new(rom))),
+        3 => Rc::new(RefCell::new(Cnrom::new(rom))),
+        4 => Rc::new(RefCell::new(Mmc3::new(rom))),
+        7 => Rc::new(RefCell::new(Axrom::new(rom))),
+        66 => Rc::new(RefCell::new(Gxrom::new(rom))),
+        _ => panic!("unsupported mapper {}", rom.mapper),
+    }
+}
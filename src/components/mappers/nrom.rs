@@ -0,0 +1,105 @@
+use super::{Chr, PrgRam};
+use crate::components::cartridge::{Mapper, Mirroring, Rom};
+
+// NROM (mapper 0): no bank switching, 16K or 32K PRG and 8K CHR
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_ram: PrgRam,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Self {
+        Nrom {
+            prg_rom: rom.prg_rom,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            prg_ram: PrgRam::new(rom.prg_ram_size + rom.prg_nvram_size, rom.battery),
+            mirroring: rom.screen_mirroring,
+        }
+    }
+
+    pub fn with_chr(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        Nrom {
+            prg_rom: vec![0; 0x4000],
+            chr: Chr::new(chr_rom, 0),
+            prg_ram: PrgRam::new(0, false),
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram.read(address),
+            0x8000..=0xFFFF => {
+                let mut address = address - 0x8000;
+                if self.prg_rom.len() == 0x4000 && address >= 0x4000 {
+                    //mirror if needed
+                    address %= 0x4000;
+                }
+                self.prg_rom[address as usize]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        // NROM has no registers, writes to ROM space are ignored
+        if let 0x6000..=0x7FFF = address {
+            self.prg_ram.write(address, data);
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.chr.write(address as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        self.prg_ram.save_data()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::components::cartridge::test::nrom;
+
+    #[test]
+    fn test_16k_prg_is_mirrored() {
+        let mut pgp_rom = vec![0; 0x4000];
+        pgp_rom[0x0000] = 0x11;
+        pgp_rom[0x3FFF] = 0x22;
+        let mut mapper = Nrom::new(nrom(pgp_rom));
+
+        assert_eq!(mapper.cpu_read(0x8000), 0x11);
+        assert_eq!(mapper.cpu_read(0xC000), 0x11);
+        assert_eq!(mapper.cpu_read(0xFFFF), 0x22);
+    }
+
+    #[test]
+    fn test_32k_prg_and_ignored_writes() {
+        let mut pgp_rom = vec![0; 0x8000];
+        pgp_rom[0x0000] = 0x11;
+        pgp_rom[0x4000] = 0x22;
+        let mut mapper = Nrom::new(nrom(pgp_rom));
+
+        assert_eq!(mapper.cpu_read(0xC000), 0x22);
+        mapper.cpu_write(0x8000, 0x33);
+        assert_eq!(mapper.cpu_read(0x8000), 0x11);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    }
+}
@@ -0,0 +1,93 @@
+use super::{Chr, PrgRam};
+use crate::components::cartridge::{Mapper, Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+// UxROM (mapper 2): switchable 16K bank at $8000, last bank fixed at $C000
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_ram: PrgRam,
+    mirroring: Mirroring,
+    prg_bank: u8,
+}
+
+impl Uxrom {
+    pub fn new(rom: Rom) -> Self {
+        Uxrom {
+            prg_rom: rom.prg_rom,
+            chr: Chr::new(rom.chr_rom, rom.chr_ram_size),
+            prg_ram: PrgRam::new(rom.prg_ram_size + rom.prg_nvram_size, rom.battery),
+            mirroring: rom.screen_mirroring,
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let bank = match address {
+            0x6000..=0x7FFF => return self.prg_ram.read(address),
+            0x8000..=0xBFFF => self.prg_bank as usize % banks,
+            0xC000..=0xFFFF => banks - 1,
+            _ => return 0,
+        };
+        self.prg_rom[bank * PRG_BANK_SIZE + (address as usize & 0x3FFF)]
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram.write(address, data),
+            0x8000..=0xFFFF => self.prg_bank = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.chr.write(address as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        self.prg_ram.save_data()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.prg_ram.load(data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::components::cartridge::test::{banks, create_rom, TestRom};
+
+    #[test]
+    fn test_prg_banking() {
+        let pgp_rom = banks(0..8, PRG_BANK_SIZE);
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x08, 0x01, 0x20, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom,
+            chr_rom: vec![0; 0x2000],
+        });
+        let mut mapper = Uxrom::new(Rom::new(&raw).unwrap());
+
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 7);
+
+        mapper.cpu_write(0x8000, 3);
+        assert_eq!(mapper.cpu_read(0xBFFF), 3);
+        assert_eq!(mapper.cpu_read(0xFFFF), 7);
+    }
+}
@@ -1,5 +1,7 @@
 pub mod ppu;
+pub mod apu;
 pub mod cartridge;
+pub mod mappers;
 pub mod bus;
 pub mod assembly;
 pub mod cpu;
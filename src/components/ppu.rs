@@ -1,61 +1,13 @@
-use super::cartridge::Mirroring;
+use std::cell::RefCell;
+use std::rc::Rc;
 
-pub struct AddressRegister {
-    low: u8,
-    high: u8,
-    high_pointer: bool,
-}
-
-impl AddressRegister {
-    pub fn new() -> Self {
-        AddressRegister {
-            high: 0,
-            low: 0,
-            high_pointer: true,
-        }
-    }
+use super::cartridge::{Mapper, Mirroring, Region};
+use super::mappers::nrom::Nrom;
+use crate::render::palette::Palette;
+use crate::render::Frame;
 
-    fn set(&mut self, data: u16) {
-        self.high = (data >> 8) as u8;
-        self.low = (data & 0xff) as u8;
-    }
-
-    pub fn update(&mut self, data: u8) {
-        if self.high_pointer {
-            self.high = data;
-        } else {
-            self.low = data;
-        }
-
-        if self.get() > 0x3fff {
-            //mirror down addr above 0x3fff
-            self.set(self.get() & 0b11111111111111);
-        }
-
-        self.high_pointer = !self.high_pointer;
-    }
-
-    pub fn increment(&mut self, inc: u8) {
-        let low = self.low;
-        self.low = self.low.wrapping_add(inc);
-
-        if low > self.low {
-            self.high = self.high.wrapping_add(1);
-        }
-
-        if self.get() > 0x3fff {
-            self.set(self.get() & 0b11111111111111); //mirror down addr above 0x3fff
-        }
-    }
-
-    pub fn reset_latch(&mut self) {
-        self.high_pointer = true;
-    }
-
-    pub fn get(&self) -> u16 {
-        ((self.high as u16) << 8) | (self.low as u16)
-    }
-}
+const DOTS_PER_SCANLINE: usize = 341;
+const SPRITES_PER_SCANLINE: usize = 8;
 
 bitflags! {
 
@@ -228,35 +180,6 @@ impl MaskRegister {
     }
 }
 
-pub struct ScrollRegister {
-    pub scroll_x: u8,
-    pub scroll_y: u8,
-    pub latch: bool,
-}
-
-impl ScrollRegister {
-    pub fn new() -> Self {
-        ScrollRegister {
-            scroll_x: 0,
-            scroll_y: 0,
-            latch: false,
-        }
-    }
-
-    pub fn write(&mut self, data: u8) {
-        if !self.latch {
-            self.scroll_x = data;
-        } else {
-            self.scroll_y = data;
-        }
-        self.latch = !self.latch;
-    }
-
-    pub fn reset_latch(&mut self) {
-        self.latch = false;
-    }
-}
-
 bitflags! {
 
     // 7  bit  0
@@ -321,14 +244,43 @@ impl StatusRegister {
     }
 }
 
+// Palette RAM is 32 bytes mirrored up to $3FFF, and $3F10/$3F14/$3F18/$3F1C
+// are mirrors of $3F00/$3F04/$3F08/$3F0C
+fn palette_index(address: u16) -> usize {
+    let index = (address & 0x1f) as usize;
+    if index >= 0x10 && index & 0b11 == 0 {
+        index - 0x10
+    } else {
+        index
+    }
+}
+
+// A sprite picked by evaluation for the scanline being drawn, with its
+// pattern row already fetched (and flipped horizontally if needed)
+struct LineSprite {
+    zero: bool,
+    x: u8,
+    attributes: u8,
+    pattern_low: u8,
+    pattern_high: u8,
+}
+
+impl LineSprite {
+    fn pixel(&self, x: usize) -> u8 {
+        let column = x.wrapping_sub(self.x as usize);
+        if column > 7 {
+            return 0;
+        }
+        let shift = 7 - column;
+        ((self.pattern_high >> shift) & 1) << 1 | ((self.pattern_low >> shift) & 1)
+    }
+}
+
 pub struct PPU {
-    pub chr_rom: Vec<u8>,
-    pub mirroring: Mirroring,
+    pub mapper: Rc<RefCell<dyn Mapper>>,
     pub control: ControlRegister,
     pub mask: MaskRegister,
     pub status: StatusRegister,
-    pub scroll: ScrollRegister,
-    pub address: AddressRegister,
     pub vram: [u8; 2048],
 
     pub oam_address: u8,
@@ -339,24 +291,58 @@ pub struct PPU {
 
     pub scanline: u16,
     cycles: usize,
+    odd_frame: bool,
+    region: Region,
     pub nmi_interrupt: Option<u8>,
+
+    // Internal scroll registers, v is the current VRAM address and t the
+    // temporary one that $2000/$2005/$2006 writes assemble
+    //
+    // 14 bit   0
+    // yyy NN YYYYY XXXXX
+    // ||| || ||||| +++++- coarse X scroll
+    // ||| || +++++------- coarse Y scroll
+    // ||| ++------------- nametable select
+    // +++---------------- fine Y scroll
+    v: u16,
+    t: u16,
+    fine_x: u8,
+    // first/second write toggle shared by $2005 and $2006
+    write_toggle: bool,
+
+    next_tile_id: u8,
+    next_tile_attribute: u8,
+    next_tile_low: u8,
+    next_tile_high: u8,
+    pattern_shift_low: u16,
+    pattern_shift_high: u16,
+    attribute_shift_low: u16,
+    attribute_shift_high: u16,
+
+    line_sprites: Vec<LineSprite>,
+    // when false every sprite on a line is drawn, overflow is still reported
+    pub sprite_limit: bool,
+
+    pub palette: Palette,
+    pub frame: Frame,
 }
 
 impl PPU {
     pub fn new_empty_rom() -> Self {
-        PPU::new(vec![0; 2048], Mirroring::Horizontal)
+        PPU::new_with_mirroring(Mirroring::Horizontal)
+    }
+
+    pub fn new_with_mirroring(mirroring: Mirroring) -> Self {
+        PPU::new(Rc::new(RefCell::new(Nrom::with_chr(vec![0; 2048], mirroring))))
     }
 
-    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+    pub fn new(mapper: Rc<RefCell<dyn Mapper>>) -> Self {
         PPU {
-            chr_rom: chr_rom,
-            mirroring: mirroring,
+            mapper,
             control: ControlRegister::new(),
             mask: MaskRegister::new(),
             status: StatusRegister::new(),
             oam_address: 0,
-            scroll: ScrollRegister::new(),
-            address: AddressRegister::new(),
             vram: [0; 2048],
             oam_data: [0; 64 * 4],
             palette_table: [0; 32],
@@ -364,7 +350,27 @@ impl PPU {
 
             cycles: 0,
             scanline: 0,
+            odd_frame: false,
+            region: Region::Ntsc,
             nmi_interrupt: None,
+
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            write_toggle: false,
+            next_tile_id: 0,
+            next_tile_attribute: 0,
+            next_tile_low: 0,
+            next_tile_high: 0,
+            pattern_shift_low: 0,
+            pattern_shift_high: 0,
+            attribute_shift_low: 0,
+            attribute_shift_high: 0,
+            line_sprites: Vec::new(),
+            sprite_limit: true,
+
+            palette: Palette::system(),
+            frame: Frame::new(),
         }
     }
 
@@ -375,47 +381,425 @@ impl PPU {
     // Vertical:
     //   [ A ] [ B ]
     //   [ a ] [ b ]
+
+    // Single screen (lower or upper bank):
+    //   [ A ] [ a ]
+    //   [ a ] [ a ]
     pub fn mirror_vram_address(&self, address: u16) -> u16 {
         let mirrored_vram = address & 0b10111111111111; // mirror down 0x3000-0x3eff to 0x2000 - 0x2eff
         let vram_index = mirrored_vram - 0x2000; // to vram vector
         let name_table = vram_index / 0x400;
-        match (&self.mirroring, name_table) {
+        match (self.mirroring(), name_table) {
             (Mirroring::Vertical, 2) | (Mirroring::Vertical, 3) => vram_index - 0x800,
             (Mirroring::Horizontal, 2) => vram_index - 0x400,
             (Mirroring::Horizontal, 1) => vram_index - 0x400,
             (Mirroring::Horizontal, 3) => vram_index - 0x800,
+            (Mirroring::SingleScreenLower, _) => vram_index % 0x400,
+            (Mirroring::SingleScreenUpper, _) => vram_index % 0x400 + 0x400,
             _ => vram_index,
         }
     }
 
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.borrow().mirroring()
+    }
+
+    pub fn read_chr(&self, address: u16) -> u8 {
+        self.mapper.borrow_mut().ppu_read(address)
+    }
+
     fn increment_vram_address(&mut self) {
-        self.address
-            .increment(self.control.vram_address_increment());
+        // during rendering $2007 accesses bump both coarse X and Y instead
+        let rendering_line = self.scanline < 240 || self.scanline == self.pre_render_scanline();
+        if self.rendering_enabled() && rendering_line {
+            self.increment_coarse_x();
+            self.increment_y();
+        } else {
+            self.v = (self.v + self.control.vram_address_increment() as u16) & 0x7fff;
+        }
+    }
+
+    /// Dot (0-340) within the current scanline
+    pub fn dot(&self) -> usize {
+        self.cycles
     }
 
+    /// Advances the PPU by `cycles` dots, returns true once a frame is complete
     pub fn tick(&mut self, cycles: u8) -> bool {
-        self.cycles += cycles as usize;
-        if self.cycles >= 341 {
-            self.cycles = self.cycles - 341;
-            self.scanline += 1;
+        let mut frame_complete = false;
+        for _ in 0..cycles {
+            frame_complete |= self.step();
+        }
+        frame_complete
+    }
+
+    fn step(&mut self) -> bool {
+        if Some(self.cycles) == self.a12_rising_edge_dot() {
+            self.mapper.borrow_mut().ppu_a12_rising_edge();
+        }
+
+        if self.rendering_enabled() {
+            self.render_dot();
+        } else if self.scanline < 240 && (1..=256).contains(&self.cycles) {
+            self.output_pixel(self.cycles - 1, self.palette_table[0]);
+        }
 
-            if self.scanline == 241 {
+        if self.cycles == 1 {
+            if self.scanline == self.vblank_scanline() {
                 self.status.set_vblank_status(true);
-                self.status.set_sprite_zero_hit(false);
                 if self.control.generate_vblank_nmi() {
                     self.nmi_interrupt = Some(1);
                 }
+            } else if self.scanline == self.pre_render_scanline() {
+                self.status.reset_vblank_status();
+                self.status.set_sprite_zero_hit(false);
+                self.status.set_sprite_overflow(false);
             }
+        }
 
-            if self.scanline >= 262 {
+        // NTSC odd frames skip the last dot of the pre-render line while rendering
+        let skip_dot = self.region == Region::Ntsc
+            && self.scanline == self.pre_render_scanline()
+            && self.cycles == DOTS_PER_SCANLINE - 2
+            && self.odd_frame
+            && self.rendering_enabled();
+
+        self.cycles += if skip_dot { 2 } else { 1 };
+        if self.cycles >= DOTS_PER_SCANLINE {
+            self.cycles -= DOTS_PER_SCANLINE;
+            self.scanline += 1;
+
+            if self.scanline > self.pre_render_scanline() {
                 self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
                 self.nmi_interrupt = None;
-                self.status.set_sprite_zero_hit(false);
-                self.status.reset_vblank_status();
                 return true;
             }
         }
-        return false;
+        false
+    }
+
+    //          post-render  vblank starts  lines per frame
+    // NTSC     240          241            262
+    // PAL      240          241            312
+    // Dendy    240-290      291            312
+    pub fn set_region(&mut self, region: Region) {
+        self.region = match region {
+            Region::MultiRegion => Region::Ntsc,
+            region => region,
+        };
+    }
+
+    fn vblank_scanline(&self) -> u16 {
+        match self.region {
+            Region::Dendy => 291,
+            _ => 241,
+        }
+    }
+
+    fn pre_render_scanline(&self) -> u16 {
+        match self.region {
+            Region::Pal | Region::Dendy => 311,
+            _ => 261,
+        }
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask.show_background() || self.mask.show_sprites()
+    }
+
+    fn render_dot(&mut self) {
+        let dot = self.cycles;
+        let visible_line = self.scanline < 240;
+        if !visible_line && self.scanline != self.pre_render_scanline() {
+            return;
+        }
+
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.shift_background();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background_shifters();
+                    self.next_tile_id = self.read_nametable(0x2000 | (self.v & 0x0fff));
+                }
+                2 => self.fetch_attribute(),
+                4 => self.next_tile_low = self.read_background_pattern(0),
+                6 => self.next_tile_high = self.read_background_pattern(8),
+                7 => self.increment_coarse_x(),
+                _ => {}
+            }
+        }
+
+        if visible_line && (1..=256).contains(&dot) {
+            self.draw_pixel(dot - 1);
+        }
+
+        match dot {
+            256 => self.increment_y(),
+            257 => {
+                self.load_background_shifters();
+                self.copy_horizontal_scroll();
+                if visible_line {
+                    self.evaluate_sprites();
+                } else {
+                    self.line_sprites.clear();
+                }
+            }
+            280..=304 if !visible_line => self.copy_vertical_scroll(),
+            _ => {}
+        }
+    }
+
+    fn draw_pixel(&mut self, x: usize) {
+        let bit = 0x8000 >> self.fine_x;
+
+        let mut background = 0;
+        let mut background_palette = 0;
+        if self.mask.show_background() && (x >= 8 || self.mask.leftmost_8pxl_background()) {
+            background = ((self.pattern_shift_high & bit != 0) as u8) << 1
+                | (self.pattern_shift_low & bit != 0) as u8;
+            background_palette = ((self.attribute_shift_high & bit != 0) as u8) << 1
+                | (self.attribute_shift_low & bit != 0) as u8;
+        }
+
+        let mut sprite = None;
+        if self.mask.show_sprites() && (x >= 8 || self.mask.leftmost_8pxl_sprite()) {
+            sprite = self
+                .line_sprites
+                .iter()
+                .map(|s| (s.pixel(x), s.attributes))
+                .find(|(pixel, _)| *pixel != 0);
+        }
+
+        if background != 0 && self.sprite_zero_hit(x) {
+            self.status.set_sprite_zero_hit(true);
+        }
+
+        // an opaque sprite wins unless it is behind an opaque background pixel,
+        // the first opaque sprite in OAM order decides even when it is behind
+        let address = match sprite {
+            Some((pixel, attributes)) if background == 0 || attributes & 0b0010_0000 == 0 => {
+                0x3f10 + ((attributes & 0b11) * 4 + pixel) as u16
+            }
+            _ if background != 0 => 0x3f00 + (background_palette * 4 + background) as u16,
+            _ => 0x3f00,
+        };
+
+        self.output_pixel(x, self.palette_table[palette_index(address)]);
+    }
+
+    // greyscale keeps only the luma column of the palette
+    fn output_pixel(&mut self, x: usize, color: u8) {
+        let color = if self.mask.is_grayscale() {
+            color & 0x30
+        } else {
+            color & 0x3f
+        };
+        let emphasis = self.mask.bits >> 5;
+        let y = self.scanline as usize;
+
+        self.frame.set_pixel(x, y, self.palette.color(color, emphasis));
+        self.frame.set_index(x, y, (emphasis as u16) << 6 | color as u16);
+    }
+
+    // an opaque sprite 0 pixel over an opaque background pixel, except at
+    // x=255 and inside the left 8 pixels when either layer is clipped there
+    fn sprite_zero_hit(&self, x: usize) -> bool {
+        let clipped = x < 8
+            && !(self.mask.leftmost_8pxl_background() && self.mask.leftmost_8pxl_sprite());
+        if !self.mask.show_sprites() || x == 255 || clipped {
+            return false;
+        }
+
+        self.line_sprites
+            .iter()
+            .any(|sprite| sprite.zero && sprite.pixel(x) != 0)
+    }
+
+    fn shift_background(&mut self) {
+        self.pattern_shift_low <<= 1;
+        self.pattern_shift_high <<= 1;
+        self.attribute_shift_low <<= 1;
+        self.attribute_shift_high <<= 1;
+    }
+
+    // the next tile goes into the low byte, the attribute bits are
+    // expanded to a full byte so they shift in step with the pattern
+    fn load_background_shifters(&mut self) {
+        self.pattern_shift_low = (self.pattern_shift_low & 0xff00) | self.next_tile_low as u16;
+        self.pattern_shift_high = (self.pattern_shift_high & 0xff00) | self.next_tile_high as u16;
+
+        let low = if self.next_tile_attribute & 0b01 != 0 { 0xff } else { 0 };
+        let high = if self.next_tile_attribute & 0b10 != 0 { 0xff } else { 0 };
+        self.attribute_shift_low = (self.attribute_shift_low & 0xff00) | low;
+        self.attribute_shift_high = (self.attribute_shift_high & 0xff00) | high;
+    }
+
+    fn read_nametable(&self, address: u16) -> u8 {
+        self.vram[self.mirror_vram_address(address) as usize]
+    }
+
+    fn fetch_attribute(&mut self) {
+        let v = self.v;
+        let address = 0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+        let mut attribute = self.read_nametable(address);
+
+        // each attribute byte covers 4x4 tiles, two bits per 2x2 quadrant
+        if v & 0b0100_0000 != 0 {
+            attribute >>= 4;
+        }
+        if v & 0b0000_0010 != 0 {
+            attribute >>= 2;
+        }
+        self.next_tile_attribute = attribute & 0b11;
+    }
+
+    fn read_background_pattern(&self, plane: u16) -> u8 {
+        let fine_y = (self.v >> 12) & 0b111;
+        let address =
+            self.control.bknd_pattern_address() + self.next_tile_id as u16 * 16 + plane + fine_y;
+        self.read_chr(address)
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001f == 31 {
+            self.v &= !0x001f;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03e0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03e0) | (coarse_y << 5);
+    }
+
+    fn copy_horizontal_scroll(&mut self) {
+        let mask = 0x041f;
+        self.v = (self.v & !mask) | (self.t & mask);
+    }
+
+    fn copy_vertical_scroll(&mut self) {
+        let mask = 0x7be0;
+        self.v = (self.v & !mask) | (self.t & mask);
+    }
+
+    // picks the sprites for the next line and fetches their pattern rows
+    fn evaluate_sprites(&mut self) {
+        let line = self.scanline as usize;
+        let height = self.control.sprite_size() as usize;
+        let in_range = |y: u8| line.wrapping_sub(y as usize) < height;
+
+        let mut selected = Vec::new();
+        let mut n = 0;
+        while n < 64 && selected.len() < SPRITES_PER_SCANLINE {
+            if in_range(self.oam_data[n * 4]) {
+                selected.push(n);
+            }
+            n += 1;
+        }
+
+        // after eight sprites the hardware keeps looking for a ninth, but it
+        // increments the byte offset along with the sprite index, reading
+        // tile numbers, attributes and X positions as Y coordinates
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam_data[n * 4 + m]) {
+                self.status.set_sprite_overflow(true);
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 0b11;
+        }
+
+        if !self.sprite_limit {
+            selected = (0..64).filter(|&n| in_range(self.oam_data[n * 4])).collect();
+        }
+
+        self.line_sprites = selected
+            .into_iter()
+            .map(|index| self.fetch_sprite(index, line))
+            .collect();
+    }
+
+    fn fetch_sprite(&self, index: usize, line: usize) -> LineSprite {
+        let sprite = &self.oam_data[index * 4..index * 4 + 4];
+        let attributes = sprite[2];
+
+        let height = self.control.sprite_size() as usize;
+        let row = line.wrapping_sub(sprite[0] as usize);
+        let row = if attributes & 0b1000_0000 != 0 { height - 1 - row } else { row } as u16;
+
+        // 8x16 sprites take the pattern table from bit 0 of the tile index and
+        // draw tile & 0xFE on top, the next tile below it
+        let (bank, tile) = if height == 16 {
+            let tile = sprite[1] as u16;
+            ((tile & 1) * 0x1000, (tile & 0xfe) + row / 8)
+        } else {
+            (self.control.sprt_pattern_address(), sprite[1] as u16)
+        };
+        let address = bank + tile * 16 + row % 8;
+        let mut pattern_low = self.read_chr(address);
+        let mut pattern_high = self.read_chr(address + 8);
+        if attributes & 0b0100_0000 != 0 {
+            pattern_low = pattern_low.reverse_bits();
+            pattern_high = pattern_high.reverse_bits();
+        }
+
+        LineSprite {
+            zero: index == 0,
+            x: sprite[3],
+            attributes,
+            pattern_low,
+            pattern_high,
+        }
+    }
+
+    // A12 rises once per rendered line, when fetches move from the $0000
+    // pattern table to the $1000 one: at sprite fetches (dot 260) or at the
+    // prefetch of the next line's background tiles (dot 324)
+    fn a12_rising_edge_dot(&self) -> Option<usize> {
+        let rendering = self.mask.show_background() || self.mask.show_sprites();
+        let rendering_line = self.scanline < 240 || self.scanline == self.pre_render_scanline();
+        if !rendering || !rendering_line {
+            return None;
+        }
+
+        let sprites_high =
+            self.control.sprite_size() == 16 || self.control.sprt_pattern_address() == 0x1000;
+        match (self.control.bknd_pattern_address(), sprites_high) {
+            (0, true) => Some(260),
+            (0x1000, false) => Some(324),
+            _ => None,
+        }
+    }
+
+    // the reset line clears $2000, $2001, the scroll and the write toggle,
+    // VRAM, OAM and palette RAM keep their contents
+    pub fn reset(&mut self) {
+        self.control = ControlRegister::new();
+        self.mask = MaskRegister::new();
+        self.t = 0;
+        self.fine_x = 0;
+        self.write_toggle = false;
+        self.internal_data_buf = 0;
+        self.odd_frame = false;
+        self.nmi_interrupt = None;
     }
 
     pub fn poll_nmi_interrupt(&mut self) -> Option<u8> {
@@ -425,6 +809,7 @@ impl PPU {
     pub fn write_to_control(&mut self, value: u8) {
         let before_nmi_status = self.control.generate_vblank_nmi();
         self.control.update(value);
+        self.t = (self.t & !0x0c00) | ((value as u16 & 0b11) << 10);
         if !before_nmi_status && self.control.generate_vblank_nmi() && self.status.is_in_vblank() {
             self.nmi_interrupt = Some(1);
         }
@@ -437,8 +822,7 @@ impl PPU {
     pub fn read_status(&mut self) -> u8 {
         let data = self.status.snapshot();
         self.status.reset_vblank_status();
-        self.address.reset_latch();
-        self.scroll.reset_latch();
+        self.write_toggle = false;
         data
     }
 
@@ -456,29 +840,36 @@ impl PPU {
     }
 
     pub fn write_to_scroll(&mut self, value: u8) {
-        self.scroll.write(value);
+        if !self.write_toggle {
+            self.t = (self.t & !0x001f) | (value as u16 >> 3);
+            self.fine_x = value & 0b111;
+        } else {
+            self.t = (self.t & !0x73e0) | ((value as u16 & 0b111) << 12) | ((value as u16 >> 3) << 5);
+        }
+        self.write_toggle = !self.write_toggle;
     }
 
     pub fn write_to_ppu_address(&mut self, value: u8) {
-        self.address.update(value);
+        if !self.write_toggle {
+            self.t = (self.t & 0x00ff) | ((value as u16 & 0x3f) << 8);
+        } else {
+            self.t = (self.t & 0xff00) | value as u16;
+            self.v = self.t;
+        }
+        self.write_toggle = !self.write_toggle;
     }
 
     pub fn write_to_data(&mut self, value: u8) {
-        let address = self.address.get();
+        let address = self.v & 0x3fff;
         match address {
-            0..=0x1fff => println!("attempt to write to chr rom space {}", address),
-            0x2000..=0x2fff => {
+            0..=0x1fff => self.mapper.borrow_mut().ppu_write(address, value),
+            // $3000-$3EFF mirrors the nametables, v can point there after rendering
+            0x2000..=0x3eff => {
                 self.vram[self.mirror_vram_address(address) as usize] = value;
             }
-            0x3000..=0x3eff => unimplemented!("address {} shouldn't be used in reallity", address),
 
-            //Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                let add_mirror = address - 0x10;
-                self.palette_table[(add_mirror - 0x3f00) as usize] = value;
-            }
             0x3f00..=0x3fff => {
-                self.palette_table[(address - 0x3f00) as usize] = value;
+                self.palette_table[palette_index(address)] = value;
             }
             _ => panic!("unexpected access to mirrored space {}", address),
         }
@@ -486,30 +877,23 @@ impl PPU {
     }
 
     pub fn read_data(&mut self) -> u8 {
-        let address = self.address.get();
+        let address = self.v & 0x3fff;
 
         self.increment_vram_address();
 
         match address {
             0..=0x1fff => {
                 let result = self.internal_data_buf;
-                self.internal_data_buf = self.chr_rom[address as usize];
+                self.internal_data_buf = self.read_chr(address);
                 result
             }
-            0x2000..=0x2fff => {
+            0x2000..=0x3eff => {
                 let result = self.internal_data_buf;
                 self.internal_data_buf = self.vram[self.mirror_vram_address(address) as usize];
                 result
             }
-            0x3000..=0x3eff => unimplemented!("address {} shouldn't be used in reallity", address),
 
-            //Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                let add_mirror = address - 0x10;
-                self.palette_table[(add_mirror - 0x3f00) as usize]
-            }
-
-            0x3f00..=0x3fff => self.palette_table[(address - 0x3f00) as usize],
+            0x3f00..=0x3fff => self.palette_table[palette_index(address)],
             _ => panic!("unexpected access to mirrored space {}", address),
         }
     }
@@ -525,6 +909,23 @@ impl PPU {
 #[cfg(test)]
 pub mod test {
     use super::*;
+    use crate::components::cartridge::test::{create_rom, TestRom};
+    use crate::components::cartridge::Rom;
+    use crate::components::mappers;
+    use crate::render::palette::SYSTEM_PALLETE;
+
+    // NROM with 8K of CHR RAM, so tests can write their own tiles
+    fn chr_ram_ppu() -> PPU {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x00, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![0; 0x4000],
+            chr_rom: vec![],
+        });
+        PPU::new(mappers::new_mapper(Rom::new(&raw).unwrap()))
+    }
 
     #[test]
     fn test_ppu_vram_writes() {
@@ -546,7 +947,7 @@ pub mod test {
         ppu.write_to_ppu_address(0x05);
 
         ppu.read_data(); //load_into_buffer
-        assert_eq!(ppu.address.get(), 0x2306);
+        assert_eq!(ppu.v, 0x2306);
         assert_eq!(ppu.read_data(), 0x66);
     }
 
@@ -616,7 +1017,7 @@ pub mod test {
     //   [0x2800 a ] [0x2C00 b ]
     #[test]
     fn test_vram_vertical_mirror() {
-        let mut ppu = PPU::new(vec![0; 2048], Mirroring::Vertical);
+        let mut ppu = PPU::new_with_mirroring(Mirroring::Vertical);
 
         ppu.write_to_ppu_address(0x20);
         ppu.write_to_ppu_address(0x05);
@@ -673,7 +1074,22 @@ pub mod test {
 
         ppu.read_data(); //load into_buffer
         assert_eq!(ppu.read_data(), 0x66);
-        // assert_eq!(ppu.address.read(), 0x0306)
+    }
+
+    #[test]
+    fn test_ppu_vram_3000_mirror() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_control(0);
+
+        ppu.write_to_ppu_address(0x33); //0x3305 -> 0x2305
+        ppu.write_to_ppu_address(0x05);
+        ppu.write_to_data(0x66);
+        assert_eq!(ppu.vram[0x0305], 0x66);
+
+        ppu.write_to_ppu_address(0x33);
+        ppu.write_to_ppu_address(0x05);
+        ppu.read_data(); //load into_buffer
+        assert_eq!(ppu.read_data(), 0x66);
     }
 
     #[test]
@@ -687,6 +1103,376 @@ pub mod test {
         assert_eq!(ppu.status.snapshot() >> 7, 0);
     }
 
+    #[test]
+    fn test_chr_ram_writes() {
+        let mut ppu = chr_ram_ppu();
+
+        ppu.write_to_ppu_address(0x1f);
+        ppu.write_to_ppu_address(0xff);
+        ppu.write_to_data(0x66);
+
+        ppu.write_to_ppu_address(0x1f);
+        ppu.write_to_ppu_address(0xff);
+        ppu.read_data(); //load_into_buffer
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    fn run_frame(ppu: &mut PPU) {
+        while !ppu.tick(1) {}
+    }
+
+    fn pixel(ppu: &PPU, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * 256 + x) * 3;
+        let data = &ppu.frame.data;
+        (data[base], data[base + 1], data[base + 2])
+    }
+
+    #[test]
+    fn test_renders_background_and_sprites_per_dot() {
+        let mut ppu = chr_ram_ppu();
+
+        ppu.write_to_ppu_address(0x00);
+        ppu.write_to_ppu_address(0x10);
+        ppu.write_to_data(0xff); // tile 1, first row, low plane
+
+        ppu.write_to_ppu_address(0x20);
+        ppu.write_to_ppu_address(0x00);
+        ppu.write_to_data(0x01);
+
+        ppu.write_to_ppu_address(0x3f);
+        ppu.write_to_ppu_address(0x00);
+        ppu.write_to_data(0x0f);
+        ppu.write_to_data(0x30);
+        ppu.write_to_ppu_address(0x3f);
+        ppu.write_to_ppu_address(0x11);
+        ppu.write_to_data(0x16);
+        ppu.write_to_ppu_address(0x00);
+        ppu.write_to_ppu_address(0x00);
+
+        ppu.write_oam_dma(&[0xff; 256]);
+        ppu.oam_data[..4].copy_from_slice(&[9, 0x01, 0x00, 20]);
+
+        ppu.write_to_mask(0b0001_1110);
+        run_frame(&mut ppu);
+        run_frame(&mut ppu);
+
+        assert_eq!(pixel(&ppu, 0, 0), SYSTEM_PALLETE[0x30]);
+        assert_eq!(pixel(&ppu, 7, 0), SYSTEM_PALLETE[0x30]);
+        assert_eq!(pixel(&ppu, 8, 0), SYSTEM_PALLETE[0x0f]);
+        assert_eq!(pixel(&ppu, 0, 1), SYSTEM_PALLETE[0x0f]);
+
+        assert_eq!(pixel(&ppu, 20, 10), SYSTEM_PALLETE[0x16]);
+        assert_eq!(pixel(&ppu, 27, 10), SYSTEM_PALLETE[0x16]);
+        assert_eq!(pixel(&ppu, 28, 10), SYSTEM_PALLETE[0x0f]);
+        assert_eq!(pixel(&ppu, 20, 11), SYSTEM_PALLETE[0x0f]);
+
+        ppu.write_to_scroll(4);
+        ppu.write_to_scroll(0);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 3, 0), SYSTEM_PALLETE[0x30]);
+        assert_eq!(pixel(&ppu, 4, 0), SYSTEM_PALLETE[0x0f]);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let mut ppu = chr_ram_ppu();
+
+        // tile 1 is solid, the whole nametable uses it
+        ppu.write_to_ppu_address(0x00);
+        ppu.write_to_ppu_address(0x10);
+        for _ in 0..8 {
+            ppu.write_to_data(0xff);
+        }
+        ppu.write_to_ppu_address(0x20);
+        ppu.write_to_ppu_address(0x00);
+        for _ in 0..0x3c0 {
+            ppu.write_to_data(0x01);
+        }
+        ppu.write_to_ppu_address(0x00);
+        ppu.write_to_ppu_address(0x00);
+
+        ppu.write_oam_dma(&[0xff; 256]);
+        ppu.oam_data[..4].copy_from_slice(&[30, 0x01, 0x00, 255]);
+        ppu.write_to_mask(0b0001_1110);
+        run_frame(&mut ppu);
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT)); // x=255 never hits
+
+        ppu.oam_data[..4].copy_from_slice(&[30, 0x01, 0x00, 0]);
+        ppu.write_to_mask(0b0001_1000); // left 8 pixels clipped
+        run_frame(&mut ppu);
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+
+        ppu.oam_data[..4].copy_from_slice(&[30, 0x01, 0x00, 100]);
+        while ppu.scanline != 31 {
+            ppu.tick(1);
+        }
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+        while ppu.scanline != 32 {
+            ppu.tick(1);
+        }
+        assert!(ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+
+        while ppu.scanline != ppu.pre_render_scanline() {
+            ppu.tick(1);
+        }
+        ppu.tick(2);
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+    }
+
+    fn sprites_on_line(ppu: &mut PPU, line: u16) -> usize {
+        while ppu.scanline != line || ppu.cycles != 258 {
+            ppu.tick(1);
+        }
+        ppu.line_sprites.len()
+    }
+
+    #[test]
+    fn test_sprite_limit_and_overflow() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_oam_dma(&[0xff; 256]);
+        for i in 0..9 {
+            ppu.oam_data[i * 4] = 20;
+        }
+        ppu.write_to_mask(0b0001_0000);
+
+        assert_eq!(sprites_on_line(&mut ppu, 20), 8);
+        assert!(ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+
+        ppu.sprite_limit = false;
+        assert_eq!(sprites_on_line(&mut ppu, 21), 9);
+    }
+
+    #[test]
+    fn test_sprite_overflow_diagonal_evaluation() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_oam_dma(&[0xff; 256]);
+        for i in 0..8 {
+            ppu.oam_data[i * 4] = 20;
+        }
+        // the ninth sprite is out of range, so the tenth is checked by its
+        // tile number byte, which happens to be in range
+        ppu.oam_data[9 * 4 + 1] = 20;
+        ppu.write_to_mask(0b0001_0000);
+
+        assert_eq!(sprites_on_line(&mut ppu, 20), 8);
+        assert!(ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+
+        // a real ninth sprite that the skewed reads miss
+        ppu.oam_data[9 * 4 + 1] = 0xff;
+        ppu.oam_data[10 * 4] = 20;
+        while ppu.scanline != ppu.pre_render_scanline() {
+            ppu.tick(1);
+        }
+        assert_eq!(sprites_on_line(&mut ppu, 20), 8);
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+    }
+
+    #[test]
+    fn test_8x16_sprites() {
+        let mut ppu = chr_ram_ppu();
+
+        // $1000 bank: tile 2 row 0 is 0x80, tile 3 row 7 is 0x01
+        ppu.write_to_ppu_address(0x10);
+        ppu.write_to_ppu_address(0x20);
+        ppu.write_to_data(0x80);
+        ppu.write_to_ppu_address(0x10);
+        ppu.write_to_ppu_address(0x37);
+        ppu.write_to_data(0x01);
+        ppu.write_to_ppu_address(0x00);
+        ppu.write_to_ppu_address(0x00);
+
+        ppu.write_oam_dma(&[0xff; 256]);
+        ppu.oam_data[..4].copy_from_slice(&[19, 0x03, 0x00, 40]);
+        ppu.write_to_control(0b0010_0000);
+        ppu.write_to_mask(0b0001_0000);
+
+        sprites_on_line(&mut ppu, 19);
+        assert_eq!(ppu.line_sprites[0].pattern_low, 0x80);
+        sprites_on_line(&mut ppu, 34);
+        assert_eq!(ppu.line_sprites[0].pattern_low, 0x01);
+
+        // flipped both ways, the bottom-right pixel moves to the top-left
+        ppu.oam_data[2] = 0b1100_0000;
+        sprites_on_line(&mut ppu, 19);
+        assert_eq!(ppu.line_sprites[0].pattern_low, 0x80);
+        sprites_on_line(&mut ppu, 34);
+        assert_eq!(ppu.line_sprites[0].pattern_low, 0x01);
+        sprites_on_line(&mut ppu, 35);
+        assert_eq!(ppu.line_sprites.len(), 0);
+    }
+
+    #[test]
+    fn test_palette_mirroring() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_ppu_address(0x3f);
+        ppu.write_to_ppu_address(0x10);
+        ppu.write_to_data(0x21);
+
+        ppu.write_to_ppu_address(0x3f);
+        ppu.write_to_ppu_address(0x00);
+        assert_eq!(ppu.read_data(), 0x21);
+
+        ppu.write_to_ppu_address(0x3f);
+        ppu.write_to_ppu_address(0xe4);
+        ppu.write_to_data(0x22);
+        assert_eq!(ppu.palette_table[0x04], 0x22);
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        let mut ppu = chr_ram_ppu();
+
+        // tile 1: left half opaque
+        ppu.write_to_ppu_address(0x00);
+        ppu.write_to_ppu_address(0x10);
+        for row in 0..16 {
+            ppu.write_to_data(if row < 8 { 0xf0 } else { 0x00 });
+        }
+        // tile 2: solid
+        for _ in 0..8 {
+            ppu.write_to_data(0xff);
+        }
+        ppu.write_to_ppu_address(0x20);
+        ppu.write_to_ppu_address(0x00);
+        for _ in 0..0x3c0 {
+            ppu.write_to_data(0x01);
+        }
+
+        ppu.write_to_ppu_address(0x3f);
+        ppu.write_to_ppu_address(0x00);
+        for color in [0x0f, 0x30, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00] {
+            ppu.write_to_data(color);
+        }
+        ppu.write_to_ppu_address(0x3f);
+        ppu.write_to_ppu_address(0x11);
+        ppu.write_to_data(0x16);
+        ppu.write_to_ppu_address(0x3f);
+        ppu.write_to_ppu_address(0x15);
+        ppu.write_to_data(0x2a);
+        ppu.write_to_ppu_address(0x00);
+        ppu.write_to_ppu_address(0x00);
+
+        ppu.write_oam_dma(&[0xff; 256]);
+        // a back-priority sprite in front of a front-priority one
+        ppu.oam_data[..8].copy_from_slice(&[9, 0x02, 0b0010_0000, 16, 9, 0x02, 0b0000_0001, 16]);
+        ppu.write_to_mask(0b0001_1110);
+        run_frame(&mut ppu);
+        run_frame(&mut ppu);
+
+        // behind opaque background: background shows, the lower sprite never does
+        assert_eq!(pixel(&ppu, 16, 10), SYSTEM_PALLETE[0x30]);
+        // over transparent background: the back-priority sprite shows
+        assert_eq!(pixel(&ppu, 20, 10), SYSTEM_PALLETE[0x16]);
+        // plain backdrop
+        assert_eq!(pixel(&ppu, 4, 0), SYSTEM_PALLETE[0x0f]);
+    }
+
+    #[test]
+    fn test_mask_greyscale_emphasis_and_clipping() {
+        let mut ppu = chr_ram_ppu();
+
+        ppu.write_to_ppu_address(0x00);
+        ppu.write_to_ppu_address(0x10);
+        for _ in 0..8 {
+            ppu.write_to_data(0xff);
+        }
+        ppu.write_to_ppu_address(0x20);
+        ppu.write_to_ppu_address(0x00);
+        for _ in 0..0x3c0 {
+            ppu.write_to_data(0x01);
+        }
+        ppu.write_to_ppu_address(0x3f);
+        ppu.write_to_ppu_address(0x00);
+        ppu.write_to_data(0x0f);
+        ppu.write_to_data(0x16);
+        ppu.write_to_ppu_address(0x00);
+        ppu.write_to_ppu_address(0x00);
+        ppu.write_oam_dma(&[0xff; 256]);
+
+        ppu.write_to_mask(0b0000_1000); // background without the left column
+        run_frame(&mut ppu);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 7, 0), SYSTEM_PALLETE[0x0f]);
+        assert_eq!(pixel(&ppu, 8, 0), SYSTEM_PALLETE[0x16]);
+
+        ppu.write_to_mask(0b0000_1011);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), SYSTEM_PALLETE[0x10]);
+
+        ppu.write_to_mask(0b0010_0000); // emphasise red with rendering disabled
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), (0x05, 0x04, 0x04));
+
+        ppu.write_to_mask(0b0110_0000); // emphasise red and green
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), (0x04, 0x04, 0x03));
+    }
+
+    #[test]
+    fn test_scroll_and_address_writes_share_t() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_control(0b10);
+        assert_eq!(ppu.t, 0x0800);
+
+        ppu.read_status();
+        ppu.write_to_scroll(0b0111_1101);
+        assert_eq!(ppu.t, 0x080f);
+        assert_eq!(ppu.fine_x, 0b101);
+
+        ppu.write_to_scroll(0b0101_1110);
+        assert_eq!(ppu.t, 0x696f);
+
+        ppu.write_to_ppu_address(0x3d);
+        assert_eq!(ppu.t, 0x3d6f);
+        ppu.write_to_ppu_address(0xf0);
+        assert_eq!(ppu.t, 0x3df0);
+        assert_eq!(ppu.v, 0x3df0);
+    }
+
+    #[test]
+    fn test_vblank_and_nmi_timing() {
+        let mut ppu = PPU::new_empty_rom();
+        ppu.write_to_control(0b1000_0000);
+
+        while ppu.scanline != ppu.vblank_scanline() {
+            ppu.tick(1);
+        }
+        assert!(!ppu.status.is_in_vblank());
+        ppu.tick(2);
+        assert!(ppu.status.is_in_vblank());
+        assert_eq!(ppu.poll_nmi_interrupt(), Some(1));
+
+        while ppu.scanline != ppu.pre_render_scanline() {
+            ppu.tick(1);
+        }
+        ppu.tick(2);
+        assert!(!ppu.status.is_in_vblank());
+    }
+
+    #[test]
+    fn test_region_frame_length() {
+        let dots_per_frame = |ppu: &mut PPU| {
+            let mut dots = 0;
+            while !ppu.tick(1) {
+                dots += 1;
+            }
+            dots + 1
+        };
+
+        let mut ppu = PPU::new_empty_rom();
+        assert_eq!(dots_per_frame(&mut ppu), 341 * 262);
+
+        ppu.set_region(Region::Pal);
+        assert_eq!(dots_per_frame(&mut ppu), 341 * 312);
+
+        ppu.set_region(Region::Dendy);
+        ppu.write_to_control(0b1000_0000);
+        while ppu.poll_nmi_interrupt().is_none() {
+            ppu.tick(1);
+        }
+        assert_eq!(ppu.scanline, 291);
+    }
+
     #[test]
     fn test_oam_read_write() {
         let mut ppu = PPU::new_empty_rom();
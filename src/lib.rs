@@ -1,16 +1,21 @@
+pub mod blargg;
 pub mod components;
+pub mod nestest;
 pub mod render;
 pub mod trace;
 
 use std::collections::HashMap;
 
+use components::apu::{self, APU};
 use components::bus::BUS;
-use components::cartridge::Rom;
+use components::cartridge::{Region, Rom};
 use components::cpu::CPU;
 use components::joypads::{Joypad, JoypadButton};
 use components::ppu::PPU;
-use render::Frame;
+use render::ntsc::{self, NtscFilter};
+use render::palette::{NtscParams, Palette};
 
+use sdl2::audio::AudioSpecDesired;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::PixelFormatEnum;
@@ -23,7 +28,51 @@ extern crate lazy_static;
 #[macro_use]
 extern crate bitflags;
 
-pub fn run(game: &str) {
+// battery-backed RAM is flushed to disk every 5 seconds of emulated time
+const SAVE_INTERVAL_SECONDS: u32 = 5;
+
+// drop samples instead of letting the audio queue (and latency) grow unbounded
+const MAX_QUEUED_AUDIO_BYTES: u32 = apu::SAMPLE_RATE / 10 * 4;
+
+// what --ntsc-sharpness falls back to when it is not given
+const DEFAULT_NTSC_SHARPNESS: f64 = 0.5;
+
+pub struct Options {
+    // draw every sprite on a scanline instead of the hardware's eight (no flicker)
+    pub no_sprite_limit: bool,
+    // replaces the built-in palette, loaded from a .pal file or generated
+    pub palette: Option<Palette>,
+    // start with the NTSC composite filter on, N toggles it while running
+    pub ntsc_filter: bool,
+    pub ntsc_sharpness: f64,
+    // the TV knobs of the generated palette, the filter decodes with the same ones
+    pub ntsc_params: NtscParams,
+    // overrides the region from the ROM header
+    pub region: Option<Region>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            no_sprite_limit: false,
+            palette: None,
+            ntsc_filter: false,
+            ntsc_sharpness: DEFAULT_NTSC_SHARPNESS,
+            ntsc_params: NtscParams::default(),
+            region: None,
+        }
+    }
+}
+
+fn save_game(data: Option<&[u8]>, path: &str) {
+    if let Some(data) = data {
+        if let Err(err) = std::fs::write(path, data) {
+            println!("Could not write save file {}: {}", path, err);
+        }
+    }
+}
+
+pub fn run(game: &str, options: &Options) {
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let window = video_subsystem
@@ -36,16 +85,37 @@ pub fn run(game: &str) {
     let mut event_pump = sdl_context.event_pump().unwrap();
     canvas.set_scale(3.0, 3.0).unwrap();
 
+    let audio_subsystem = sdl_context.audio().unwrap();
+    let audio_spec = AudioSpecDesired {
+        freq: Some(apu::SAMPLE_RATE as i32),
+        channels: Some(1),
+        samples: Some(1024),
+    };
+    let audio_queue = audio_subsystem
+        .open_queue::<f32, _>(None, &audio_spec)
+        .unwrap();
+    audio_queue.resume();
+
     let creator = canvas.texture_creator();
     let mut texture = creator
         .create_texture_target(PixelFormatEnum::RGB24, 256, 240)
         .unwrap();
+    let mut ntsc_texture = creator
+        .create_texture_target(
+            PixelFormatEnum::RGB24,
+            ntsc::OUTPUT_WIDTH as u32,
+            ntsc::OUTPUT_HEIGHT as u32,
+        )
+        .unwrap();
+    let mut ntsc_filter = NtscFilter::new(options.ntsc_params.clone(), options.ntsc_sharpness);
+    let mut ntsc_enabled = options.ntsc_filter;
 
     let path_to_game = format!("games/{}.nes", game);
+    let path_to_save = format!("games/{}.sav", game);
     let bytes: Vec<u8> = std::fs::read(path_to_game).unwrap();
     let rom = Rom::new(&bytes).unwrap();
-
-    let mut frame = Frame::new();
+    // only cartridges with a battery keep PRG RAM in a .sav file
+    let battery = rom.battery;
 
     let mut keymap = HashMap::new();
     keymap.insert(Keycode::W, JoypadButton::UP);
@@ -57,14 +127,32 @@ pub fn run(game: &str) {
     keymap.insert(Keycode::Return, JoypadButton::START);
     keymap.insert(Keycode::Tab, JoypadButton::SELECT);
 
-    let mut fps = FpsClock::new(60);
-    let bus = BUS::new(rom, move |ppu: &PPU, joypad: &mut Joypad| {
-
-        render::render(ppu, &mut frame);
-
-        texture.update(None, &frame.data, 256 * 3).unwrap();
+    let region = options.region.unwrap_or(rom.region);
+    let frame_rate = match region {
+        Region::Pal | Region::Dendy => 50,
+        Region::Ntsc | Region::MultiRegion => 60,
+    };
+    let mut fps = FpsClock::new(frame_rate);
+    let save_interval_frames = frame_rate * SAVE_INTERVAL_SECONDS;
+    let mut frames_since_save = 0;
+    let save_path = path_to_save.clone();
+    let mut bus = BUS::new(rom, move |ppu: &PPU, apu: &mut APU, joypad: &mut Joypad| {
+
+        let samples = apu.take_samples();
+        if audio_queue.size() < MAX_QUEUED_AUDIO_BYTES {
+            audio_queue.queue_audio(&samples).unwrap();
+        }
 
-        canvas.copy(&texture, None, None).unwrap();
+        if ntsc_enabled {
+            let output = ntsc_filter.apply(&ppu.frame);
+            ntsc_texture
+                .update(None, output, ntsc::OUTPUT_WIDTH * 3)
+                .unwrap();
+            canvas.copy(&ntsc_texture, None, None).unwrap();
+        } else {
+            texture.update(None, &ppu.frame.data, 256 * 3).unwrap();
+            canvas.copy(&texture, None, None).unwrap();
+        }
         canvas.present();
 
         for event in event_pump.poll_iter() {
@@ -73,7 +161,18 @@ pub fn run(game: &str) {
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => std::process::exit(0),
+                } => {
+                    if battery {
+                        save_game(ppu.mapper.borrow().save_data(), &save_path);
+                    }
+                    std::process::exit(0)
+                }
+
+                Event::KeyDown {
+                    keycode: Some(Keycode::N),
+                    repeat: false,
+                    ..
+                } => ntsc_enabled = !ntsc_enabled,
 
                 Event::KeyDown { keycode, .. } => {
                     if let Some(key) = keymap.get(&keycode.unwrap_or(Keycode::Ampersand)) {
@@ -90,11 +189,34 @@ pub fn run(game: &str) {
                 _ => { /* do nothing */ }
             }
         }
+
+        frames_since_save += 1;
+        if battery && frames_since_save >= save_interval_frames {
+            save_game(ppu.mapper.borrow().save_data(), &save_path);
+            frames_since_save = 0;
+        }
+
         fps.tick();
     });
 
+    if battery {
+        if let Ok(save) = std::fs::read(&path_to_save) {
+            bus.load_save_data(&save);
+        }
+    }
+    bus.set_region(region);
+    bus.set_sprite_limit(!options.no_sprite_limit);
+    if let Some(palette) = &options.palette {
+        bus.set_palette(palette.clone());
+    }
+
     let mut cpu = CPU::new(bus);
 
     cpu.reset();
     cpu.run();
+
+    // run only returns when the CPU halts, keep what was played since the last save
+    if battery {
+        save_game(cpu.bus.save_data().as_deref(), &path_to_save);
+    }
 }
@@ -1,6 +1,73 @@
 use std::io::stdin;
 
+use nes::components::cartridge::{Region, Rom};
+use nes::render::palette::{NtscParams, Palette};
+
+// --ntsc-palette=hue,saturation,contrast,brightness, the NTSC filter uses them too
+fn parse_ntsc_params(args: &[String]) -> NtscParams {
+    let values = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--ntsc-palette="));
+    let values = match values {
+        Some(values) => values,
+        None => return NtscParams::default(),
+    };
+    let values: Vec<f64> = values
+        .split(',')
+        .map(|value| value.parse().expect("--ntsc-palette values must be numbers"))
+        .collect();
+    if let [hue, saturation, contrast, brightness] = values[..] {
+        NtscParams {
+            hue,
+            saturation,
+            contrast,
+            brightness,
+        }
+    } else {
+        panic!("--ntsc-palette expects hue,saturation,contrast,brightness");
+    }
+}
+
+// --palette <file.pal> or --ntsc-palette[=hue,saturation,contrast,brightness]
+fn parse_palette(args: &[String], ntsc_params: &NtscParams) -> Option<Palette> {
+    if let Some(position) = args.iter().position(|arg| arg == "--palette") {
+        let path = args.get(position + 1).expect("--palette needs a .pal file");
+        return Some(Palette::load(path).unwrap());
+    }
+
+    args.iter()
+        .any(|arg| arg.starts_with("--ntsc-palette"))
+        .then(|| Palette::ntsc(ntsc_params))
+}
+
+// --region=ntsc|pal|dendy
+fn parse_region(args: &[String]) -> Option<Region> {
+    let region = args.iter().find_map(|arg| arg.strip_prefix("--region="))?;
+    match region {
+        "ntsc" => Some(Region::Ntsc),
+        "pal" => Some(Region::Pal),
+        "dendy" => Some(Region::Dendy),
+        _ => panic!("unknown region {}, expected ntsc, pal or dendy", region),
+    }
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    // --test-rom <file.nes>: run a blargg test ROM headless and report the result
+    if let Some(position) = args.iter().position(|arg| arg == "--test-rom") {
+        let path = args.get(position + 1).expect("--test-rom needs a .nes file");
+        let raw = std::fs::read(path).unwrap();
+        match nes::blargg::run(Rom::new(&raw).unwrap(), 60) {
+            Ok(message) => println!("passed\n{}", message),
+            Err(report) => {
+                println!("{}", report);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     let mut game = String::new();
 
     println!("Enter the name of game (without symbols and spaces)");
@@ -8,5 +75,23 @@ fn main() {
 
     game = game.trim().to_lowercase();
 
-    nes::run(&game);
+    let ntsc_params = parse_ntsc_params(&args);
+    let mut options = nes::Options {
+        no_sprite_limit: args.iter().any(|arg| arg == "--no-sprite-limit"),
+        palette: parse_palette(&args, &ntsc_params),
+        ntsc_filter: args.iter().any(|arg| arg == "--ntsc-filter"),
+        ntsc_params,
+        region: parse_region(&args),
+        ..Default::default()
+    };
+    let sharpness = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--ntsc-sharpness="));
+    if let Some(sharpness) = sharpness {
+        options.ntsc_sharpness = sharpness
+            .parse()
+            .expect("--ntsc-sharpness must be a number");
+    }
+
+    nes::run(&game, &options);
 }
@@ -0,0 +1,236 @@
+use crate::components::apu::APU;
+use crate::components::assembly::Assembler;
+use crate::components::bus::BUS;
+use crate::components::cartridge::Rom;
+use crate::components::cpu::CPU;
+use crate::components::joypads::Joypad;
+use crate::components::ppu::PPU;
+use crate::trace::trace;
+
+// nestest runs every test without a screen when started at $C000 instead of the
+// reset vector, and leaves its error codes at $02 and $03 (00 = all passed)
+const AUTOMATION_START: u16 = 0xC000;
+
+// matched lines printed before the first divergence
+const CONTEXT_LINES: usize = 5;
+
+// instructions the harness may skip to reach the first reference line, some
+// logs leave out the initial JMP $C5F5
+const MAX_SKIPPED_LINES: usize = 16;
+
+/// Runs nestest in automation mode and compares the trace of every instruction
+/// with the reference log, line by line.
+///
+/// Returns the number of matched lines, or a report of the first divergence.
+/// Only the columns present in the reference are compared, so a log without
+/// cycle counts still matches a trace that has them.
+pub fn run(rom: Rom, reference: &str) -> Result<usize, String> {
+    let bus = BUS::new(rom, |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {});
+    let mut cpu = CPU::new(bus);
+    cpu.reset();
+    cpu.register_pc = AUTOMATION_START;
+
+    let reference: Vec<&str> = reference
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let first = match reference.first() {
+        Some(line) => line_address(line)?,
+        None => return Err(String::from("reference log is empty")),
+    };
+
+    let assembler = Assembler::new();
+    let mut skipped = 0;
+    while cpu.register_pc != first {
+        if skipped == MAX_SKIPPED_LINES || cpu.step(&assembler, &mut |_| {}) {
+            return Err(format!(
+                "reference log starts at ${:04X}, never reached",
+                first
+            ));
+        }
+        skipped += 1;
+    }
+
+    for (n, expected) in reference.iter().enumerate() {
+        let mut actual = String::new();
+        let halted = cpu.step(&assembler, &mut |cpu| actual = trace(cpu));
+
+        if !lines_match(expected, &actual) {
+            let context = &reference[n.saturating_sub(CONTEXT_LINES)..n];
+            let codes = (cpu.memory_read(0x02), cpu.memory_read(0x03));
+            return Err(divergence_report(n + 1, context, expected, &actual, codes));
+        }
+
+        if halted && n + 1 < reference.len() {
+            return Err(format!(
+                "CPU halted at line {} of {}",
+                n + 1,
+                reference.len()
+            ));
+        }
+    }
+
+    Ok(reference.len())
+}
+
+fn line_address(line: &str) -> Result<u16, String> {
+    line.get(0..4)
+        .and_then(|address| u16::from_str_radix(address, 16).ok())
+        .ok_or(format!("reference line has no address: {}", line))
+}
+
+// "C5F5  A2 00     LDX #$00   A:00 X:00 ... SP:FD" => ("C5F5  A2 00     LDX #$00", fields)
+fn split_line(line: &str) -> (&str, Vec<(&str, String)>) {
+    let registers = line.find("A:").unwrap_or(line.len());
+    let (disassembly, registers) = line.split_at(registers);
+
+    // values may contain spaces ("PPU:  0, 21"), everything up to the next
+    // "KEY:" belongs to the current field
+    let mut fields: Vec<(&str, String)> = vec![];
+    for token in registers.split_whitespace() {
+        match token.split_once(':') {
+            Some((key, value)) => fields.push((key, value.to_string())),
+            None => {
+                if let Some((_, value)) = fields.last_mut() {
+                    if !value.is_empty() {
+                        value.push(' ');
+                    }
+                    value.push_str(token);
+                }
+            }
+        }
+    }
+
+    (disassembly.trim_end(), fields)
+}
+
+fn lines_match(expected: &str, actual: &str) -> bool {
+    let (expected_asm, expected_fields) = split_line(expected);
+    let (actual_asm, actual_fields) = split_line(actual);
+
+    expected_asm == actual_asm
+        && expected_fields
+            .iter()
+            .all(|field| actual_fields.contains(field))
+}
+
+// one "KEY: expected xx, got yy" line per column that differs
+fn register_diff(expected: &str, actual: &str) -> Vec<String> {
+    let (_, expected_fields) = split_line(expected);
+    let (_, actual_fields) = split_line(actual);
+
+    expected_fields
+        .iter()
+        .filter_map(|(key, value)| {
+            let got = actual_fields
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str());
+            match got {
+                Some(got) if got == value => None,
+                Some(got) => Some(format!("{}: expected {}, got {}", key, value, got)),
+                None => Some(format!("{}: expected {}, missing", key, value)),
+            }
+        })
+        .collect()
+}
+
+fn divergence_report(
+    line: usize,
+    context: &[&str],
+    expected: &str,
+    actual: &str,
+    codes: (u8, u8),
+) -> String {
+    let mut report = vec![format!("nestest diverged at line {}:", line)];
+    report.extend(context.iter().map(|line| format!("  {}", line)));
+    report.push(format!("- {}", expected));
+    report.push(format!("+ {}", actual));
+
+    let (expected_asm, _) = split_line(expected);
+    let (actual_asm, _) = split_line(actual);
+    if expected_asm != actual_asm {
+        report.push(String::from("  instruction differs"));
+    }
+    report.extend(
+        register_diff(expected, actual)
+            .iter()
+            .map(|diff| format!("  {}", diff)),
+    );
+    report.push(format!(
+        "  nestest error codes: $02={:02X} $03={:02X}",
+        codes.0, codes.1
+    ));
+
+    report.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::components::cartridge::test::nrom;
+    use std::fs;
+
+    // NROM with LDX #$01, DEX, DEY at $C000
+    fn program_rom() -> Rom {
+        let mut pgp_rom = vec![0xEA; 0x4000];
+        pgp_rom[0..4].copy_from_slice(&[0xA2, 0x01, 0xCA, 0x88]);
+        nrom(pgp_rom)
+    }
+
+    const REFERENCE: &str = "\
+C000  A2 01     LDX #$01                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C002  CA        DEX                             A:00 X:01 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9
+C003  88        DEY                             A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 33 CYC:11
+";
+
+    #[test]
+    fn test_matching_log() {
+        assert_eq!(run(program_rom(), REFERENCE), Ok(3));
+        // leading lines missing from the log are skipped
+        let without_jump: Vec<&str> = REFERENCE.lines().skip(1).collect();
+        assert_eq!(run(program_rom(), &without_jump.join("\n")), Ok(2));
+    }
+
+    #[test]
+    fn test_reports_first_divergence() {
+        let reference = REFERENCE.replace("A:00 X:00 Y:00 P:26", "A:00 X:02 Y:00 P:26");
+        let report = run(program_rom(), &reference).unwrap_err();
+
+        assert!(report.starts_with("nestest diverged at line 3:"));
+        assert!(report.contains("  C002  CA        DEX"));
+        assert!(report.contains("  X: expected 02, got 00"));
+        assert!(!report.contains("instruction differs"));
+    }
+
+    #[test]
+    fn test_extra_columns() {
+        let expected = "C000  A2 01     LDX #$01                        A:00 X:00 Y:00 P:24 SP:FD";
+        let actual = format!("{} PPU:  0, 21 CYC:7", expected);
+        assert!(lines_match(expected, &actual));
+
+        let expected = format!("{} PPU:  0, 30 CYC:10", expected);
+        assert!(!lines_match(&expected, &actual));
+        assert_eq!(
+            register_diff(&expected, &actual),
+            vec!["PPU: expected 0, 30, got 0, 21", "CYC: expected 10, got 7"]
+        );
+    }
+
+    // needs games/nestest.nes, which is not shipped with the repo:
+    // cargo test nestest -- --ignored
+    // the full Nintendulator log (with PPU and CYC columns) is used when it is
+    // saved as logs/nestest.log, the register-only log otherwise
+    #[test]
+    #[ignore]
+    fn test_nestest() {
+        let raw = fs::read("games/nestest.nes").expect("games/nestest.nes not found");
+        let reference = fs::read_to_string("logs/nestest.log")
+            .or_else(|_| fs::read_to_string("logs/nestest_no_cycle.log"))
+            .unwrap();
+
+        if let Err(report) = run(Rom::new(&raw).unwrap(), &reference) {
+            panic!("{}", report);
+        }
+    }
+}
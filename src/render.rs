@@ -1,74 +1,11 @@
-use crate::components::ppu::PPU;
-
-pub static SYSTEM_PALLETE: [(u8, u8, u8); 64] = [
-    (0x80, 0x80, 0x80),
-    (0x00, 0x3D, 0xA6),
-    (0x00, 0x12, 0xB0),
-    (0x44, 0x00, 0x96),
-    (0xA1, 0x00, 0x5E),
-    (0xC7, 0x00, 0x28),
-    (0xBA, 0x06, 0x00),
-    (0x8C, 0x17, 0x00),
-    (0x5C, 0x2F, 0x00),
-    (0x10, 0x45, 0x00),
-    (0x05, 0x4A, 0x00),
-    (0x00, 0x47, 0x2E),
-    (0x00, 0x41, 0x66),
-    (0x00, 0x00, 0x00),
-    (0x05, 0x05, 0x05),
-    (0x05, 0x05, 0x05),
-    (0xC7, 0xC7, 0xC7),
-    (0x00, 0x77, 0xFF),
-    (0x21, 0x55, 0xFF),
-    (0x82, 0x37, 0xFA),
-    (0xEB, 0x2F, 0xB5),
-    (0xFF, 0x29, 0x50),
-    (0xFF, 0x22, 0x00),
-    (0xD6, 0x32, 0x00),
-    (0xC4, 0x62, 0x00),
-    (0x35, 0x80, 0x00),
-    (0x05, 0x8F, 0x00),
-    (0x00, 0x8A, 0x55),
-    (0x00, 0x99, 0xCC),
-    (0x21, 0x21, 0x21),
-    (0x09, 0x09, 0x09),
-    (0x09, 0x09, 0x09),
-    (0xFF, 0xFF, 0xFF),
-    (0x0F, 0xD7, 0xFF),
-    (0x69, 0xA2, 0xFF),
-    (0xD4, 0x80, 0xFF),
-    (0xFF, 0x45, 0xF3),
-    (0xFF, 0x61, 0x8B),
-    (0xFF, 0x88, 0x33),
-    (0xFF, 0x9C, 0x12),
-    (0xFA, 0xBC, 0x20),
-    (0x9F, 0xE3, 0x0E),
-    (0x2B, 0xF0, 0x35),
-    (0x0C, 0xF0, 0xA4),
-    (0x05, 0xFB, 0xFF),
-    (0x5E, 0x5E, 0x5E),
-    (0x0D, 0x0D, 0x0D),
-    (0x0D, 0x0D, 0x0D),
-    (0xFF, 0xFF, 0xFF),
-    (0xA6, 0xFC, 0xFF),
-    (0xB3, 0xEC, 0xFF),
-    (0xDA, 0xAB, 0xEB),
-    (0xFF, 0xA8, 0xF9),
-    (0xFF, 0xAB, 0xB3),
-    (0xFF, 0xD2, 0xB0),
-    (0xFF, 0xEF, 0xA6),
-    (0xFF, 0xF7, 0x9C),
-    (0xD7, 0xE8, 0x95),
-    (0xA6, 0xED, 0xAF),
-    (0xA2, 0xF2, 0xDA),
-    (0x99, 0xFF, 0xFC),
-    (0xDD, 0xDD, 0xDD),
-    (0x11, 0x11, 0x11),
-    (0x11, 0x11, 0x11),
-];
+pub mod ntsc;
+pub mod palette;
 
 pub struct Frame {
     pub data: Vec<u8>,
+    // the palette index behind every pixel with the emphasis bits on top
+    // (eee pppppp), for output stages that work on the NES video signal
+    pub indices: Vec<u16>,
 }
 
 impl Frame {
@@ -78,6 +15,7 @@ impl Frame {
     pub fn new() -> Self {
         Frame {
             data: vec![0; (Frame::WIDTH) * (Frame::HIGHT) * 3],
+            indices: vec![0; (Frame::WIDTH) * (Frame::HIGHT)],
         }
     }
 
@@ -89,112 +27,10 @@ impl Frame {
             self.data[base + 2] = rgb.2;
         }
     }
-}
-
-fn bg_pallette(ppu: &PPU, tile_column: usize, tile_row: usize) -> [u8; 4] {
-    let attr_table_idx = tile_row / 4 * 8 + tile_column / 4;
-    let attr_byte = ppu.vram[0x3c0 + attr_table_idx]; // note: still using hardcoded first nametable
-
-    let pallet_idx = match (tile_column % 4 / 2, tile_row % 4 / 2) {
-        (0, 0) => attr_byte & 0b11,
-        (1, 0) => (attr_byte >> 2) & 0b11,
-        (0, 1) => (attr_byte >> 4) & 0b11,
-        (1, 1) => (attr_byte >> 6) & 0b11,
-        (_, _) => panic!("should not happen"),
-    };
-
-    let pallete_start: usize = 1 + (pallet_idx as usize) * 4;
-    [
-        ppu.palette_table[0],
-        ppu.palette_table[pallete_start],
-        ppu.palette_table[pallete_start + 1],
-        ppu.palette_table[pallete_start + 2],
-    ]
-}
-
-fn sprite_palette(ppu: &PPU, pallete_idx: u8) -> [u8; 4] {
-    let start = 0x11 + (pallete_idx * 4) as usize;
-    [
-        0,
-        ppu.palette_table[start],
-        ppu.palette_table[start + 1],
-        ppu.palette_table[start + 2],
-    ]
-}
-
-pub fn render(ppu: &PPU, frame: &mut Frame) {
-    let bank = ppu.control.bknd_pattern_address();
-
-    for i in 0..0x3c0 {
-        let tile = ppu.vram[i] as u16;
-        let tile_column = i % 32;
-        let tile_row = i / 32;
-        let tile = &ppu.chr_rom[(bank + tile * 16) as usize..=(bank + tile * 16 + 15) as usize];
-        let palette = bg_pallette(ppu, tile_column, tile_row);
-
-        for y in 0..=7 {
-            let mut upper = tile[y];
-            let mut lower = tile[y + 8];
-
-            for x in (0..=7).rev() {
-                let value = (1 & lower) << 1 | (1 & upper);
-                upper = upper >> 1;
-                lower = lower >> 1;
-                let rgb = match value {
-                    0 => SYSTEM_PALLETE[ppu.palette_table[0] as usize],
-                    1 => SYSTEM_PALLETE[palette[1] as usize],
-                    2 => SYSTEM_PALLETE[palette[2] as usize],
-                    3 => SYSTEM_PALLETE[palette[3] as usize],
-                    _ => panic!("can't be"),
-                };
-                frame.set_pixel(tile_column * 8 + x, tile_row * 8 + y, rgb)
-            }
-        }
-    }
-
-    for i in (0..ppu.oam_data.len()).step_by(4).rev() {
-        let tile_idx = ppu.oam_data[i + 1] as u16;
-        let tile_x = ppu.oam_data[i + 3] as usize;
-        let tile_y = ppu.oam_data[i] as usize;
-
-        let flip_vertical = if ppu.oam_data[i + 2] >> 7 & 1 == 1 {
-            true
-        } else {
-            false
-        };
-        let flip_horizontal = if ppu.oam_data[i + 2] >> 6 & 1 == 1 {
-            true
-        } else {
-            false
-        };
-        let pallette_idx = ppu.oam_data[i + 2] & 0b11;
-        let sprite_palette = sprite_palette(ppu, pallette_idx);
-        let bank: u16 = ppu.control.sprt_pattern_address();
-
-        let tile =
-            &ppu.chr_rom[(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize];
 
-        for y in 0..=7 {
-            let mut upper = tile[y];
-            let mut lower = tile[y + 8];
-            'ololo: for x in (0..=7).rev() {
-                let value = (1 & lower) << 1 | (1 & upper);
-                upper = upper >> 1;
-                lower = lower >> 1;
-                let rgb = match value {
-                    0 => continue 'ololo, // skip coloring the pixel
-                    1 => SYSTEM_PALLETE[sprite_palette[1] as usize],
-                    2 => SYSTEM_PALLETE[sprite_palette[2] as usize],
-                    3 => SYSTEM_PALLETE[sprite_palette[3] as usize],
-                    _ => panic!("can't be"),
-                };
-                match (flip_horizontal, flip_vertical) {
-                    (false, false) => frame.set_pixel(tile_x + x, tile_y + y, rgb),
-                    (true, false) => frame.set_pixel(tile_x + 7 - x, tile_y + y, rgb),
-                    (false, true) => frame.set_pixel(tile_x + x, tile_y + 7 - y, rgb),
-                    (true, true) => frame.set_pixel(tile_x + 7 - x, tile_y + 7 - y, rgb),
-                }
-            }
+    pub fn set_index(&mut self, x: usize, y: usize, index: u16) {
+        if let Some(pixel) = self.indices.get_mut(y * Frame::WIDTH + x) {
+            *pixel = index;
         }
     }
 }
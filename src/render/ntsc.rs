@@ -0,0 +1,162 @@
+use super::palette::{phase_angle, signal_level, yiq_to_rgb, NtscParams};
+use super::Frame;
+
+// the PPU outputs one signal sample per master clock, 8 per pixel, and the
+// color subcarrier repeats every 12 of them
+const SAMPLES_PER_PIXEL: usize = 8;
+const SAMPLES_PER_LINE: usize = Frame::WIDTH * SAMPLES_PER_PIXEL;
+const SUBCARRIER_CYCLE: usize = 12;
+
+// same 256 -> 602 widening as blargg's nes_ntsc
+pub const OUTPUT_WIDTH: usize = 602;
+pub const OUTPUT_HEIGHT: usize = Frame::HIGHT;
+
+/// Re-encodes a frame into the composite signal the PPU generates and decodes
+/// it like a TV would, bringing back color fringing and dot crawl
+pub struct NtscFilter {
+    pub params: NtscParams,
+    // 0.0 averages luma over a whole subcarrier cycle (soft, no chroma dots),
+    // 1.0 over a third of one (sharp, visible chroma dots)
+    pub sharpness: f64,
+    frame_count: usize,
+
+    luma: Vec<f64>,
+    in_phase: Vec<f64>,
+    quadrature: Vec<f64>,
+    pub output: Vec<u8>,
+}
+
+impl NtscFilter {
+    pub fn new(params: NtscParams, sharpness: f64) -> Self {
+        NtscFilter {
+            params,
+            sharpness,
+            frame_count: 0,
+            luma: vec![0.0; SAMPLES_PER_LINE + 1],
+            in_phase: vec![0.0; SAMPLES_PER_LINE + 1],
+            quadrature: vec![0.0; SAMPLES_PER_LINE + 1],
+            output: vec![0; OUTPUT_WIDTH * OUTPUT_HEIGHT * 3],
+        }
+    }
+
+    pub fn apply(&mut self, frame: &Frame) -> &[u8] {
+        // each line starts 4 phases later (341 dots * 8 samples), and the
+        // starting phase drifting between frames is what shows up as dot crawl
+        let frame_phase = (self.frame_count % 3) * 4;
+        self.frame_count += 1;
+
+        let mut carrier = [(0.0, 0.0); SUBCARRIER_CYCLE];
+        for (phase, wave) in carrier.iter_mut().enumerate() {
+            let angle = phase_angle(phase as u8, &self.params);
+            *wave = (angle.cos() * 2.0, angle.sin() * 2.0);
+        }
+
+        let luma_window =
+            (SUBCARRIER_CYCLE as f64 * (1.0 - self.sharpness.clamp(0.0, 1.0) * 2.0 / 3.0)) as usize;
+
+        for y in 0..OUTPUT_HEIGHT {
+            let line_phase = frame_phase + y * 4;
+
+            // running sums of the signal and of its products with the subcarrier
+            for sample in 0..SAMPLES_PER_LINE {
+                let index = frame.indices[y * Frame::WIDTH + sample / SAMPLES_PER_PIXEL];
+                let phase = (line_phase + sample) % SUBCARRIER_CYCLE;
+                let level = signal_level(index as u8 & 0x3f, (index >> 6) as u8, phase as u8);
+
+                self.luma[sample + 1] = self.luma[sample] + level;
+                self.in_phase[sample + 1] = self.in_phase[sample] + level * carrier[phase].0;
+                self.quadrature[sample + 1] = self.quadrature[sample] + level * carrier[phase].1;
+            }
+
+            for x in 0..OUTPUT_WIDTH {
+                let center = (x * 2 + 1) * SAMPLES_PER_LINE / (OUTPUT_WIDTH * 2);
+                let average = |sums: &[f64], width: usize| {
+                    let start = center.saturating_sub(width / 2).min(SAMPLES_PER_LINE - width);
+                    (sums[start + width] - sums[start]) / width as f64
+                };
+
+                let rgb = yiq_to_rgb(
+                    average(&self.luma, luma_window),
+                    average(&self.in_phase, SUBCARRIER_CYCLE),
+                    average(&self.quadrature, SUBCARRIER_CYCLE),
+                    &self.params,
+                );
+
+                let base = (y * OUTPUT_WIDTH + x) * 3;
+                self.output[base] = rgb.0;
+                self.output[base + 1] = rgb.1;
+                self.output[base + 2] = rgb.2;
+            }
+        }
+
+        &self.output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::render::palette::Palette;
+
+    fn flat_frame(index: u16) -> Frame {
+        let mut frame = Frame::new();
+        for pixel in frame.indices.iter_mut() {
+            *pixel = index;
+        }
+        frame
+    }
+
+    fn output_pixel(output: &[u8], x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * OUTPUT_WIDTH + x) * 3;
+        (output[base], output[base + 1], output[base + 2])
+    }
+
+    #[test]
+    fn test_flat_colors_match_ntsc_palette() {
+        let palette = Palette::ntsc(&NtscParams::default());
+        let mut filter = NtscFilter::new(NtscParams::default(), 0.0);
+
+        for index in [0x0f, 0x16, 0x21, 0x2a, 0x30, 0x16 | 0b001 << 6] {
+            let output = filter.apply(&flat_frame(index));
+            let expected = palette.color(index as u8 & 0x3f, (index >> 6) as u8);
+            let (red, green, blue) = output_pixel(output, 300, 120);
+            assert!((red as i16 - expected.0 as i16).abs() <= 2);
+            assert!((green as i16 - expected.1 as i16).abs() <= 2);
+            assert!((blue as i16 - expected.2 as i16).abs() <= 2);
+        }
+    }
+
+    #[test]
+    fn test_edges_produce_color_artifacts() {
+        // alternating black and white columns pick up chroma on a TV
+        let mut frame = flat_frame(0x0f);
+        for (i, pixel) in frame.indices.iter_mut().enumerate() {
+            if i % 2 == 0 {
+                *pixel = 0x30;
+            }
+        }
+
+        let mut filter = NtscFilter::new(NtscParams::default(), 1.0);
+        let output = filter.apply(&frame);
+        let colored = (0..OUTPUT_WIDTH).any(|x| {
+            let (red, green, blue) = output_pixel(output, x, 100);
+            red != green || green != blue
+        });
+        assert!(colored);
+    }
+
+    #[test]
+    fn test_dot_crawl_changes_between_frames() {
+        let mut frame = flat_frame(0x0f);
+        for (i, pixel) in frame.indices.iter_mut().enumerate() {
+            if i % 3 == 0 {
+                *pixel = 0x30;
+            }
+        }
+
+        let mut filter = NtscFilter::new(NtscParams::default(), 0.5);
+        let first = filter.apply(&frame).to_vec();
+        let second = filter.apply(&frame).to_vec();
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,316 @@
+use std::f64::consts::PI;
+
+// how much an emphasis bit dims the two other color channels
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+pub static SYSTEM_PALLETE: [(u8, u8, u8); 64] = [
+    (0x80, 0x80, 0x80),
+    (0x00, 0x3D, 0xA6),
+    (0x00, 0x12, 0xB0),
+    (0x44, 0x00, 0x96),
+    (0xA1, 0x00, 0x5E),
+    (0xC7, 0x00, 0x28),
+    (0xBA, 0x06, 0x00),
+    (0x8C, 0x17, 0x00),
+    (0x5C, 0x2F, 0x00),
+    (0x10, 0x45, 0x00),
+    (0x05, 0x4A, 0x00),
+    (0x00, 0x47, 0x2E),
+    (0x00, 0x41, 0x66),
+    (0x00, 0x00, 0x00),
+    (0x05, 0x05, 0x05),
+    (0x05, 0x05, 0x05),
+    (0xC7, 0xC7, 0xC7),
+    (0x00, 0x77, 0xFF),
+    (0x21, 0x55, 0xFF),
+    (0x82, 0x37, 0xFA),
+    (0xEB, 0x2F, 0xB5),
+    (0xFF, 0x29, 0x50),
+    (0xFF, 0x22, 0x00),
+    (0xD6, 0x32, 0x00),
+    (0xC4, 0x62, 0x00),
+    (0x35, 0x80, 0x00),
+    (0x05, 0x8F, 0x00),
+    (0x00, 0x8A, 0x55),
+    (0x00, 0x99, 0xCC),
+    (0x21, 0x21, 0x21),
+    (0x09, 0x09, 0x09),
+    (0x09, 0x09, 0x09),
+    (0xFF, 0xFF, 0xFF),
+    (0x0F, 0xD7, 0xFF),
+    (0x69, 0xA2, 0xFF),
+    (0xD4, 0x80, 0xFF),
+    (0xFF, 0x45, 0xF3),
+    (0xFF, 0x61, 0x8B),
+    (0xFF, 0x88, 0x33),
+    (0xFF, 0x9C, 0x12),
+    (0xFA, 0xBC, 0x20),
+    (0x9F, 0xE3, 0x0E),
+    (0x2B, 0xF0, 0x35),
+    (0x0C, 0xF0, 0xA4),
+    (0x05, 0xFB, 0xFF),
+    (0x5E, 0x5E, 0x5E),
+    (0x0D, 0x0D, 0x0D),
+    (0x0D, 0x0D, 0x0D),
+    (0xFF, 0xFF, 0xFF),
+    (0xA6, 0xFC, 0xFF),
+    (0xB3, 0xEC, 0xFF),
+    (0xDA, 0xAB, 0xEB),
+    (0xFF, 0xA8, 0xF9),
+    (0xFF, 0xAB, 0xB3),
+    (0xFF, 0xD2, 0xB0),
+    (0xFF, 0xEF, 0xA6),
+    (0xFF, 0xF7, 0x9C),
+    (0xD7, 0xE8, 0x95),
+    (0xA6, 0xED, 0xAF),
+    (0xA2, 0xF2, 0xDA),
+    (0x99, 0xFF, 0xFC),
+    (0xDD, 0xDD, 0xDD),
+    (0x11, 0x11, 0x11),
+    (0x11, 0x11, 0x11),
+];
+
+// Composite signal levels of the 2C02 relative to sync, for luma rows 0-3
+// (http://wiki.nesdev.com/w/index.php/NTSC_video)
+const SIGNAL_LOW: [f64; 4] = [0.228, 0.312, 0.552, 0.880];
+const SIGNAL_HIGH: [f64; 4] = [0.616, 0.840, 1.100, 1.100];
+const SIGNAL_BLACK: f64 = 0.312;
+const SIGNAL_WHITE: f64 = 1.100;
+// emphasis lowers the signal by this much during the emphasised color's phase
+const SIGNAL_EMPHASIS_ATTENUATION: f64 = 0.746;
+
+/// Knobs for `Palette::ntsc`, the same ones a TV has
+#[derive(Clone)]
+pub struct NtscParams {
+    // degrees
+    pub hue: f64,
+    pub saturation: f64,
+    pub contrast: f64,
+    pub brightness: f64,
+}
+
+impl Default for NtscParams {
+    fn default() -> Self {
+        NtscParams {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+        }
+    }
+}
+
+/// 64 colors for each of the 8 combinations of the PPUMASK emphasis bits
+#[derive(Clone)]
+pub struct Palette {
+    colors: Vec<[(u8, u8, u8); 64]>,
+}
+
+impl Palette {
+    pub fn system() -> Self {
+        Palette::with_emphasis(&SYSTEM_PALLETE)
+    }
+
+    // derives the emphasis tables by dimming each channel once for every
+    // emphasised bit other than its own
+    fn with_emphasis(base: &[(u8, u8, u8); 64]) -> Self {
+        let colors = (0..8u8)
+            .map(|emphasis| {
+                let dim =
+                    |own: u8| EMPHASIS_ATTENUATION.powi((emphasis & !own).count_ones() as i32);
+                let red = dim(0b001);
+                let green = dim(0b010);
+                let blue = dim(0b100);
+
+                let mut table = *base;
+                if emphasis != 0 {
+                    for rgb in table.iter_mut() {
+                        *rgb = (
+                            (rgb.0 as f32 * red) as u8,
+                            (rgb.1 as f32 * green) as u8,
+                            (rgb.2 as f32 * blue) as u8,
+                        );
+                    }
+                }
+                table
+            })
+            .collect();
+
+        Palette { colors }
+    }
+
+    /// Parses a `.pal` file: 64 RGB triplets (192 bytes), optionally followed
+    /// by the 7 emphasis variants (1536 bytes)
+    pub fn from_bytes(raw: &[u8]) -> Result<Palette, String> {
+        let table = |chunk: &[u8]| {
+            let mut table = [(0, 0, 0); 64];
+            for (color, rgb) in table.iter_mut().zip(chunk.chunks(3)) {
+                *color = (rgb[0], rgb[1], rgb[2]);
+            }
+            table
+        };
+
+        match raw.len() {
+            192 => Ok(Palette::with_emphasis(&table(raw))),
+            1536 => Ok(Palette {
+                colors: raw.chunks(192).map(table).collect(),
+            }),
+            size => Err(format!(
+                "Palette file must be 192 or 1536 bytes, got {}",
+                size
+            )),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Palette, String> {
+        let raw = std::fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+        Palette::from_bytes(&raw)
+    }
+
+    /// Decodes the composite signal the PPU would generate for every color
+    /// the way an NTSC TV does (YIQ over one 12-sample color subcarrier cycle)
+    pub fn ntsc(params: &NtscParams) -> Palette {
+        let colors = (0..8u8)
+            .map(|emphasis| {
+                let mut table = [(0, 0, 0); 64];
+                for (index, rgb) in table.iter_mut().enumerate() {
+                    *rgb = ntsc_color(index as u8, emphasis, params);
+                }
+                table
+            })
+            .collect();
+
+        Palette { colors }
+    }
+
+    /// `emphasis` is bits 5-7 of PPUMASK shifted down (bit 0 red, 1 green, 2 blue)
+    pub fn color(&self, index: u8, emphasis: u8) -> (u8, u8, u8) {
+        self.colors[(emphasis & 0b111) as usize][(index & 0x3f) as usize]
+    }
+}
+
+// the signal is high for 6 of the 12 subcarrier phases, shifted by the hue
+fn in_color_phase(hue: u8, phase: u8) -> bool {
+    (hue + phase) % 12 < 6
+}
+
+/// Normalized composite level (0 = black, 1 = white) the PPU outputs for a
+/// color at one of the 12 subcarrier phases
+pub(crate) fn signal_level(index: u8, emphasis: u8, phase: u8) -> f64 {
+    let hue = index & 0x0f;
+    let luma = ((index >> 4) & 0b11) as usize;
+    let (low, high) = match hue {
+        0x0 => (SIGNAL_HIGH[luma], SIGNAL_HIGH[luma]),
+        0xd => (SIGNAL_LOW[luma], SIGNAL_LOW[luma]),
+        0xe | 0xf => (SIGNAL_BLACK, SIGNAL_BLACK),
+        _ => (SIGNAL_LOW[luma], SIGNAL_HIGH[luma]),
+    };
+    let mut level = if in_color_phase(hue, phase) { high } else { low };
+
+    let emphasised = (emphasis & 0b001 != 0 && in_color_phase(0xc, phase))
+        || (emphasis & 0b010 != 0 && in_color_phase(0x4, phase))
+        || (emphasis & 0b100 != 0 && in_color_phase(0x8, phase));
+    if emphasised && hue < 0xe {
+        level *= SIGNAL_EMPHASIS_ATTENUATION;
+    }
+
+    (level - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK)
+}
+
+/// Subcarrier reference angle a TV demodulates I and Q against
+pub(crate) fn phase_angle(phase: u8, params: &NtscParams) -> f64 {
+    // sampled 4 phases after the color burst reference
+    PI * (phase as f64 + 4.0) / 6.0 + params.hue.to_radians()
+}
+
+/// Applies the TV controls to a demodulated YIQ color and converts it to RGB
+pub(crate) fn yiq_to_rgb(y: f64, i: f64, q: f64, params: &NtscParams) -> (u8, u8, u8) {
+    let y = y * params.contrast + params.brightness;
+    let i = i * params.saturation;
+    let q = q * params.saturation;
+
+    let to_byte = |value: f64| (value * 255.0).round().clamp(0.0, 255.0) as u8;
+    (
+        to_byte(y + 0.946882 * i + 0.623557 * q),
+        to_byte(y - 0.274788 * i - 0.635691 * q),
+        to_byte(y - 1.108545 * i + 1.709007 * q),
+    )
+}
+
+fn ntsc_color(index: u8, emphasis: u8, params: &NtscParams) -> (u8, u8, u8) {
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    for phase in 0..12 {
+        let level = signal_level(index, emphasis, phase) / 12.0;
+        let angle = phase_angle(phase, params);
+        y += level;
+        i += level * angle.cos() * 2.0;
+        q += level * angle.sin() * 2.0;
+    }
+
+    yiq_to_rgb(y, i, q, params)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_192_byte_palette() {
+        let mut raw = vec![0; 192];
+        raw[3..6].copy_from_slice(&[0x10, 0x20, 0x30]);
+
+        let palette = Palette::from_bytes(&raw).unwrap();
+        assert_eq!(palette.color(0x01, 0), (0x10, 0x20, 0x30));
+        assert_eq!(palette.color(0x41, 0), (0x10, 0x20, 0x30));
+        assert_eq!(palette.color(0x01, 0b001), (0x10, 0x1a, 0x27));
+        assert_eq!(palette.color(0x01, 0b011), (0x0d, 0x1a, 0x1f));
+        assert_eq!(palette.color(0x01, 0b111), (0x0a, 0x15, 0x1f));
+    }
+
+    #[test]
+    fn test_load_1536_byte_palette() {
+        let raw: Vec<u8> = (0..8).flat_map(|emphasis| vec![emphasis; 192]).collect();
+
+        let palette = Palette::from_bytes(&raw).unwrap();
+        assert_eq!(palette.color(0x3f, 0), (0, 0, 0));
+        assert_eq!(palette.color(0x00, 0b101), (5, 5, 5));
+    }
+
+    #[test]
+    fn test_bad_palette_size() {
+        let palette = Palette::from_bytes(&[0; 100]);
+        assert_eq!(
+            palette.err(),
+            Some("Palette file must be 192 or 1536 bytes, got 100".to_string())
+        );
+    }
+
+    #[test]
+    fn test_ntsc_palette() {
+        let palette = Palette::ntsc(&NtscParams::default());
+        assert_eq!(palette.color(0x0f, 0), (0, 0, 0));
+        assert_eq!(palette.color(0x30, 0), (255, 255, 255));
+
+        // hue 6 is red, hue 1 is blue, hue 10 is green
+        let (red, green, blue) = palette.color(0x16, 0);
+        assert!(red > green && red > blue);
+        let (red, green, blue) = palette.color(0x11, 0);
+        assert!(blue > red && blue > green);
+        let (red, green, blue) = palette.color(0x1a, 0);
+        assert!(green > red && green > blue);
+
+        // grey columns carry no color, zero saturation makes everything grey
+        let (red, green, blue) = palette.color(0x10, 0);
+        assert!(red == green && green == blue);
+        let grey = Palette::ntsc(&NtscParams {
+            saturation: 0.0,
+            ..NtscParams::default()
+        });
+        let (red, green, blue) = grey.color(0x16, 0);
+        assert!(red == green && green == blue);
+
+        // red emphasis darkens a blue color
+        let (_, _, blue) = palette.color(0x11, 0b001);
+        assert!(blue < palette.color(0x11, 0).2);
+    }
+}
@@ -126,9 +126,18 @@ pub fn trace(cpu: &mut CPU) -> String {
         .trim()
         .to_string();
 
+    let (scanline, dot) = cpu.bus.ppu_position();
     format!(
-        "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
-        asm_str, cpu.register_a, cpu.register_x, cpu.register_y, cpu.register_p, cpu.register_sp,
+        "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x} PPU:{:3},{:3} CYC:{}",
+        asm_str,
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.register_p,
+        cpu.register_sp,
+        scanline,
+        dot,
+        cpu.bus.cycles(),
     )
     .to_ascii_uppercase()
 }
@@ -136,6 +145,7 @@ pub fn trace(cpu: &mut CPU) -> String {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::components::apu::APU;
     use crate::components::bus::BUS;
     use crate::components::cartridge::test::test_rom;
     use crate::components::joypads::Joypad;
@@ -143,12 +153,12 @@ mod test {
 
     #[test]
     fn test_format_trace() {
-        let mut bus = BUS::new(test_rom(), |_ppu: &PPU, _joypad: &mut Joypad| {});
+        let mut bus = BUS::new(test_rom(), |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {});
         bus.memory_write(100, 0xa2);
         bus.memory_write(101, 0x01);
         bus.memory_write(102, 0xca);
         bus.memory_write(103, 0x88);
-        bus.memory_write(104, 0x00);
+        bus.memory_write(104, 0x02); // JAM
 
         let mut cpu = CPU::new(bus);
         cpu.register_pc = 0x64;
@@ -160,25 +170,26 @@ mod test {
             result.push(trace(cpu));
         });
         assert_eq!(
-            "0064  A2 01     LDX #$01                        A:01 X:02 Y:03 P:24 SP:FD",
+            "0064  A2 01     LDX #$01                        A:01 X:02 Y:03 P:24 SP:FD PPU:  0,  0 CYC:0",
             result[0]
         );
         assert_eq!(
-            "0066  CA        DEX                             A:01 X:01 Y:03 P:24 SP:FD",
+            "0066  CA        DEX                             A:01 X:01 Y:03 P:24 SP:FD PPU:  0,  6 CYC:2",
             result[1]
         );
         assert_eq!(
-            "0067  88        DEY                             A:01 X:00 Y:03 P:26 SP:FD",
+            "0067  88        DEY                             A:01 X:00 Y:03 P:26 SP:FD PPU:  0, 12 CYC:4",
             result[2]
         );
     }
 
     #[test]
     fn test_format_memory_access() {
-        let mut bus = BUS::new(test_rom(), |_ppu: &PPU, _joypad: &mut Joypad| {});
+        let mut bus = BUS::new(test_rom(), |_ppu: &PPU, _apu: &mut APU, _joypad: &mut Joypad| {});
         // ORA ($33), Y
         bus.memory_write(100, 0x11);
         bus.memory_write(101, 0x33);
+        bus.memory_write(102, 0x02); // JAM
 
         //data
         bus.memory_write(0x33, 00);
@@ -195,7 +206,7 @@ mod test {
             result.push(trace(cpu));
         });
         assert_eq!(
-            "0064  11 33     ORA ($33),Y = 0400 @ 0400 = AA  A:00 X:00 Y:00 P:24 SP:FD",
+            "0064  11 33     ORA ($33),Y = 0400 @ 0400 = AA  A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:0",
             result[0]
         );
     }